@@ -0,0 +1,418 @@
+use crate::cm31::{CM31Impl, CM31};
+use crate::poseidon::poseidon_hash;
+use crate::qm31::{QM31Impl, QM31};
+use lambdaworks_math::field::element::FieldElement;
+use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use lambdaworks_math::field::traits::IsField;
+use blake2::Blake2s256;
+use sha2::{Digest, Sha256};
+use std::marker::PhantomData;
+
+// Fiat-Shamir transcript: the prover mixes in everything it sends (roots, values, nonces) and
+// draws its challenges from the resulting state, the verifier replays the same calls on the
+// proof and gets the same challenges
+
+const P: u32 = (1 << 31) - 1;
+
+// fields the channel can absorb and draw, through their M31 coordinates (which the packed
+// FRI fold also works on)
+pub trait ChannelField: IsField {
+    const EXTENSION_DEGREE: usize;
+    fn to_m31s(value: &FieldElement<Self>) -> Vec<FieldElement<M31>>;
+    // the i-th of to_m31s, without the allocation
+    fn coordinate(value: &FieldElement<Self>, i: usize) -> FieldElement<M31>;
+    fn from_m31s(coordinates: &[FieldElement<M31>]) -> FieldElement<Self>;
+}
+
+impl ChannelField for M31 {
+    const EXTENSION_DEGREE: usize = 1;
+
+    fn to_m31s(value: &FieldElement<M31>) -> Vec<FieldElement<M31>> {
+        vec![*value]
+    }
+
+    fn coordinate(value: &FieldElement<M31>, i: usize) -> FieldElement<M31> {
+        assert_eq!(i, 0);
+        *value
+    }
+
+    fn from_m31s(coordinates: &[FieldElement<M31>]) -> FieldElement<M31> {
+        coordinates[0]
+    }
+}
+
+impl ChannelField for CM31 {
+    const EXTENSION_DEGREE: usize = 2;
+
+    fn to_m31s(value: &FieldElement<CM31>) -> Vec<FieldElement<M31>> {
+        vec![value.real(), value.imaginary()]
+    }
+
+    fn coordinate(value: &FieldElement<CM31>, i: usize) -> FieldElement<M31> {
+        [value.real(), value.imaginary()][i]
+    }
+
+    fn from_m31s(coordinates: &[FieldElement<M31>]) -> FieldElement<CM31> {
+        FieldElement::<CM31>::from_m31(coordinates[0], coordinates[1])
+    }
+}
+
+impl ChannelField for QM31 {
+    const EXTENSION_DEGREE: usize = 4;
+
+    fn to_m31s(value: &FieldElement<QM31>) -> Vec<FieldElement<M31>> {
+        value.to_m31_array().to_vec()
+    }
+
+    fn coordinate(value: &FieldElement<QM31>, i: usize) -> FieldElement<M31> {
+        value.to_m31_array()[i]
+    }
+
+    fn from_m31s(coordinates: &[FieldElement<M31>]) -> FieldElement<QM31> {
+        FieldElement::<QM31>::from_m31(coordinates[0], coordinates[1], coordinates[2], coordinates[3])
+    }
+}
+
+pub trait Channel {
+    type Digest;
+
+    fn digest(&self) -> Self::Digest;
+    fn mix_digest(&mut self, digest: &Self::Digest);
+    fn mix_felts<F: ChannelField>(&mut self, felts: &[FieldElement<F>]);
+    fn mix_u64(&mut self, value: u64);
+
+    // a fresh batch of random words, each uniform on [0, 2^32) or, for channels over M31,
+    // on [0, P). Every call gives new words until the next mix
+    fn draw_u32s(&mut self) -> Vec<u32>;
+
+    // hash of the current state and nonce, the proof of work of nonce is its number of
+    // leading zeros
+    fn pow_value(&self, nonce: u64) -> u64;
+
+    fn draw_felt<F: ChannelField>(&mut self) -> FieldElement<F> {
+        self.draw_felts(1).pop().unwrap()
+    }
+
+    fn draw_felts<F: ChannelField>(&mut self, n: usize) -> Vec<FieldElement<F>> {
+        let coordinates = self.draw_m31s(n * F::EXTENSION_DEGREE);
+        coordinates
+            .chunks_exact(F::EXTENSION_DEGREE)
+            .map(F::from_m31s)
+            .collect()
+    }
+
+    // uniform base field elements: 31 bits of a word, rejecting the one value that is not
+    // below P
+    fn draw_m31s(&mut self, n: usize) -> Vec<FieldElement<M31>> {
+        let mut res = Vec::with_capacity(n);
+        while res.len() < n {
+            for word in self.draw_u32s() {
+                let value = word & P;
+                if value != P && res.len() < n {
+                    res.push(FieldElement::new(value));
+                }
+            }
+        }
+        res
+    }
+
+    // n positions in a domain of size 2^log_domain_size, with repetition
+    fn draw_queries(&mut self, log_domain_size: u32, n: usize) -> Vec<usize> {
+        assert!(log_domain_size <= 31);
+        let mask = (1u32 << log_domain_size).wrapping_sub(1);
+        let mut res = Vec::with_capacity(n);
+        while res.len() < n {
+            let words = self.draw_u32s();
+            res.extend(words.iter().take(n - res.len()).map(|w| (w & mask) as usize));
+        }
+        res
+    }
+
+    fn check_pow(&self, pow_bits: u32, nonce: u64) -> bool {
+        self.pow_value(nonce).leading_zeros() >= pow_bits
+    }
+
+    // finds the first nonce with pow_bits of work and mixes it in
+    fn grind(&mut self, pow_bits: u32) -> u64 {
+        let nonce = (0..).find(|nonce| self.check_pow(pow_bits, *nonce)).unwrap();
+        self.mix_u64(nonce);
+        nonce
+    }
+
+    // verifier side of grind, mixes the nonce in like the prover did
+    fn verify_pow(&mut self, pow_bits: u32, nonce: u64) -> bool {
+        if !self.check_pow(pow_bits, nonce) {
+            return false;
+        }
+        self.mix_u64(nonce);
+        true
+    }
+}
+
+// 32 byte hashes for HashChannel (and HashMerkleHasher)
+pub trait ChannelHash {
+    fn hash(data: &[u8]) -> [u8; 32];
+}
+
+pub struct Sha256Hash;
+
+impl ChannelHash for Sha256Hash {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        Sha256::digest(data).into()
+    }
+}
+
+pub struct Blake2sHash;
+
+impl ChannelHash for Blake2sHash {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        Blake2s256::digest(data).into()
+    }
+}
+
+pub struct Blake3Hash;
+
+impl ChannelHash for Blake3Hash {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        blake3::hash(data).into()
+    }
+}
+
+// mixing data sets digest = H(digest || data), the i-th draw after it is H(digest || i || 0)
+pub struct HashChannel<H: ChannelHash> {
+    digest: [u8; 32],
+    n_draws: u32,
+    _hash: PhantomData<H>,
+}
+
+pub type Sha256Channel = HashChannel<Sha256Hash>;
+pub type Blake2sChannel = HashChannel<Blake2sHash>;
+pub type Blake3Channel = HashChannel<Blake3Hash>;
+
+impl<H: ChannelHash> HashChannel<H> {
+    pub fn new() -> Self {
+        HashChannel { digest: [0; 32], n_draws: 0, _hash: PhantomData }
+    }
+
+    // raw bytes, for data that isn't a digest or field elements
+    pub fn mix_bytes(&mut self, data: &[u8]) {
+        self.digest = H::hash(&[&self.digest[..], data].concat());
+        self.n_draws = 0;
+    }
+}
+
+impl<H: ChannelHash> Default for HashChannel<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: ChannelHash> Channel for HashChannel<H> {
+    type Digest = [u8; 32];
+
+    fn digest(&self) -> [u8; 32] {
+        self.digest
+    }
+
+    fn mix_digest(&mut self, digest: &[u8; 32]) {
+        self.mix_bytes(digest);
+    }
+
+    // canonical little endian bytes of every coordinate
+    fn mix_felts<F: ChannelField>(&mut self, felts: &[FieldElement<F>]) {
+        let bytes: Vec<u8> = felts
+            .iter()
+            .flat_map(F::to_m31s)
+            .flat_map(|c| c.representative().to_le_bytes())
+            .collect();
+        self.mix_bytes(&bytes);
+    }
+
+    fn mix_u64(&mut self, value: u64) {
+        self.mix_bytes(&value.to_le_bytes());
+    }
+
+    fn draw_u32s(&mut self) -> Vec<u32> {
+        let mut input = self.digest.to_vec();
+        input.extend(self.n_draws.to_le_bytes());
+        input.push(0);
+        self.n_draws += 1;
+        H::hash(&input)
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn pow_value(&self, nonce: u64) -> u64 {
+        let mut input = self.digest.to_vec();
+        input.extend(b"pow");
+        input.extend(nonce.to_le_bytes());
+        u64::from_be_bytes(H::hash(&input)[..8].try_into().unwrap())
+    }
+}
+
+// channel over M31 whose state is an 8 element digest and whose only primitive is
+// poseidon_hash, so that it can be replayed inside a circle STARK
+// poseidon_hash is the repo's Poseidon (see poseidon.rs), not Poseidon2-M31, so these
+// transcripts don't match Poseidon2 based ones
+// mixing coordinates pads them with a 1 and then zeros to a multiple of 8 and compresses
+// every chunk into the digest, the i-th draw after it is poseidon_hash(digest, [i, 0, ...])
+pub struct PoseidonChannel {
+    digest: [FieldElement<M31>; 8],
+    n_draws: u32,
+}
+
+impl PoseidonChannel {
+    pub fn new() -> Self {
+        PoseidonChannel { digest: [FieldElement::zero(); 8], n_draws: 0 }
+    }
+
+    fn mix_m31s(&mut self, coordinates: &[FieldElement<M31>]) {
+        let mut padded = coordinates.to_vec();
+        padded.push(FieldElement::one());
+        padded.resize(padded.len().next_multiple_of(8), FieldElement::zero());
+        for chunk in padded.chunks_exact(8) {
+            self.digest = poseidon_hash(&self.digest, chunk.try_into().unwrap());
+        }
+        self.n_draws = 0;
+    }
+
+    // 16 bits per coordinate
+    fn u64_to_m31s(value: u64) -> Vec<FieldElement<M31>> {
+        (0..4).map(|i| FieldElement::from((value >> (16 * i)) & 0xffff)).collect()
+    }
+}
+
+impl Default for PoseidonChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Channel for PoseidonChannel {
+    type Digest = [FieldElement<M31>; 8];
+
+    fn digest(&self) -> [FieldElement<M31>; 8] {
+        self.digest
+    }
+
+    fn mix_digest(&mut self, digest: &[FieldElement<M31>; 8]) {
+        self.digest = poseidon_hash(&self.digest, digest);
+        self.n_draws = 0;
+    }
+
+    fn mix_felts<F: ChannelField>(&mut self, felts: &[FieldElement<F>]) {
+        let coordinates: Vec<FieldElement<M31>> = felts.iter().flat_map(F::to_m31s).collect();
+        self.mix_m31s(&coordinates);
+    }
+
+    fn mix_u64(&mut self, value: u64) {
+        self.mix_m31s(&Self::u64_to_m31s(value));
+    }
+
+    fn draw_u32s(&mut self) -> Vec<u32> {
+        let mut input = [FieldElement::zero(); 8];
+        input[0] = FieldElement::from(self.n_draws as u64);
+        self.n_draws += 1;
+        poseidon_hash(&self.digest, &input)
+            .iter()
+            .map(|v| v.representative())
+            .collect()
+    }
+
+    // the 31 bit values of the first two elements of the hash of the nonce, one after the
+    // other from the top bit
+    fn pow_value(&self, nonce: u64) -> u64 {
+        let mut input = [FieldElement::zero(); 8];
+        input[..4].copy_from_slice(&Self::u64_to_m31s(nonce));
+        input[7] = -FieldElement::<M31>::one();
+        let out = poseidon_hash(&self.digest, &input);
+        ((out[0].representative() as u64) << 33) | ((out[1].representative() as u64) << 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcript<C: Channel>(channel: &mut C) -> (Vec<FieldElement<QM31>>, Vec<usize>) {
+        channel.mix_u64(42);
+        channel.mix_felts(&[FieldElement::<M31>::from(7), FieldElement::from(9)]);
+        let alpha: FieldElement<QM31> = channel.draw_felt();
+        channel.mix_felts(&[alpha]);
+        (channel.draw_felts(3), channel.draw_queries(10, 20))
+    }
+
+    fn check_channel<C: Channel + Default>() {
+        // same calls, same challenges
+        let (felts, queries) = transcript(&mut C::default());
+        assert_eq!((felts.clone(), queries.clone()), transcript(&mut C::default()));
+        assert!(queries.iter().all(|q| *q < 1 << 10));
+        assert_ne!(felts[0], felts[1]);
+
+        // a different message changes them
+        let mut channel = C::default();
+        channel.mix_u64(43);
+        assert_ne!(transcript(&mut channel).0, felts);
+
+        // draws without a mix in between differ
+        let mut channel = C::default();
+        let m31s = channel.draw_m31s(20);
+        assert_ne!(m31s[..10], channel.draw_m31s(10)[..]);
+
+        // the verifier accepts the prover's nonce and ends up in the same state
+        let (mut prover, mut verifier) = (C::default(), C::default());
+        let nonce = prover.grind(8);
+        assert!(C::default().check_pow(8, nonce));
+        assert!(verifier.verify_pow(8, nonce));
+        assert_eq!(prover.draw_queries(20, 4), verifier.draw_queries(20, 4));
+        // and rejects a nonce without the work
+        let lazy = (0..).find(|n| !C::default().check_pow(8, *n)).unwrap();
+        assert!(!C::default().verify_pow(8, lazy));
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // vectors from python's hashlib.blake2s, on bytes i % 251; covering the empty input and
+    // inputs around the block size
+    #[test]
+    fn blake2s_matches_the_reference_vectors() {
+        assert_eq!(
+            to_hex(&Blake2sHash::hash(b"abc")),
+            "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982"
+        );
+        let vectors = [
+            (0, "69217a3079908094e11121d042354a7c1f55b6482ca1a51e1b250dfd1ed0eef9"),
+            (3, "e8f91c6ef232a041452ab0e149070cdd7dd1769e75b3a5921be37876c45c9900"),
+            (64, "56f34e8b96557e90c1f24b52d0c89d51086acf1b00f634cf1dde9233b8eaaa3e"),
+            (65, "1b53ee94aaf34e4b159d48de352c7f0661d0a40edff95a0b1639b4090e974472"),
+            (200, "6d244e1a06ce4ef578dd0f63aff0936706735119ca9c8d22d86c801414ab9741"),
+        ];
+        for (len, expected) in vectors {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            assert_eq!(to_hex(&Blake2sHash::hash(&data)), expected);
+        }
+    }
+
+    #[test]
+    fn sha256_channel_is_deterministic_and_grinds() {
+        check_channel::<Sha256Channel>();
+    }
+
+    #[test]
+    fn blake2s_channel_is_deterministic_and_grinds() {
+        check_channel::<Blake2sChannel>();
+    }
+
+    #[test]
+    fn blake3_channel_is_deterministic_and_grinds() {
+        check_channel::<Blake3Channel>();
+    }
+
+    #[test]
+    fn poseidon_channel_is_deterministic_and_grinds() {
+        check_channel::<PoseidonChannel>();
+    }
+}
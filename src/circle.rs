@@ -2,87 +2,196 @@ use lambdaworks_math::field::element::FieldElement;
 use lambdaworks_math::field::fields::mersenne31::field::{
     Mersenne31Field as M31, MERSENNE_31_PRIME_FIELD_ORDER,
 };
-use lambdaworks_math::field::traits::IsField;
-use std::ops::Add;
+use lambdaworks_math::field::traits::{IsField, IsSubFieldOf};
+use std::ops::{Add, Mul, Neg, Sub};
 
 pub const MODULUS: u32 = MERSENNE_31_PRIME_FIELD_ORDER;
-#[derive(Clone, PartialEq, Copy)]
-pub struct CirclePoint {
-    x: FieldElement<M31>,
-    y: FieldElement<M31>,
+
+// a point on the circle x^2 + y^2 = 1 with coordinates in F
+// F is M31 for the trace/evaluation domains, and CM31 or QM31 for out of domain points
+#[derive(Clone, PartialEq, Debug)]
+pub struct CirclePoint<F: IsField = M31> {
+    x: FieldElement<F>,
+    y: FieldElement<F>,
 }
 
-impl Add for CirclePoint {
-    type Output = CirclePoint;
+impl<F: IsField> Copy for CirclePoint<F> where FieldElement<F>: Copy {}
+
+impl<F: IsField> Add for CirclePoint<F> {
+    type Output = CirclePoint<F>;
     //(x1,y1)+ (x2,y2) ---> ( x1*x2-y1*y2 , x1*y2+y1*x2 )
     fn add(self, rhs: Self) -> Self::Output {
-        let x = self.x.clone() * rhs.x.clone() - self.y.clone() * rhs.y.clone();
-        let y = self.x * rhs.y + self.y * rhs.x;
+        let x = &self.x * &rhs.x - &self.y * &rhs.y;
+        let y = &self.x * &rhs.y + &self.y * &rhs.x;
         Self { x, y }
     }
 }
-pub trait CircleImpl {
-    fn new_with_field_elements(x: FieldElement<M31>, y: FieldElement<M31>) -> CirclePoint;
-    fn new(x: u32, y: u32) -> CirclePoint;
-    fn get_x(&self) -> FieldElement<M31>;
-    fn get_y(&self) -> FieldElement<M31>;
-    fn zero() -> CirclePoint;
-    fn double(&self) -> CirclePoint;
-    fn zeroes(shape: usize) -> Vec<CirclePoint>;
-    fn inverse_x(&self) -> FieldElement<M31>;
-    fn inverse_y(&self) -> FieldElement<M31>;
+// -P is the conjugate (x,-y), since P + (x,-y) = (x^2+y^2, 0) = (1,0)
+impl<F: IsField> Neg for CirclePoint<F> {
+    type Output = CirclePoint<F>;
+    fn neg(self) -> Self::Output {
+        self.conjugate()
+    }
+}
+
+impl<F: IsField> Sub for CirclePoint<F> {
+    type Output = CirclePoint<F>;
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + (-rhs)
+    }
 }
 
-impl CircleImpl for CirclePoint {
-    fn new(x: u32, y: u32) -> CirclePoint {
-        return CirclePoint {
-            x: FieldElement::new(x),
-            y: FieldElement::new(y),
-        };
+// scalar multiplication n*P by double and add
+impl<F: IsField> Mul<u64> for CirclePoint<F> {
+    type Output = CirclePoint<F>;
+    fn mul(self, rhs: u64) -> Self::Output {
+        let mut result = Z();
+        let mut base = self;
+        let mut n = rhs;
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result + base.clone();
+            }
+            base = base.double();
+            n >>= 1;
+        }
+        result
+    }
+}
+
+pub trait CircleImpl<F: IsField = M31> {
+    fn new_with_field_elements(x: FieldElement<F>, y: FieldElement<F>) -> CirclePoint<F>;
+    #[allow(clippy::new_ret_no_self)]
+    fn new(x: u32, y: u32) -> CirclePoint<F>;
+    fn from_stereographic(t: FieldElement<F>) -> CirclePoint<F>;
+    fn get_x(&self) -> FieldElement<F>;
+    fn get_y(&self) -> FieldElement<F>;
+    fn zero() -> CirclePoint<F>;
+    fn double(&self) -> CirclePoint<F>;
+    fn conjugate(&self) -> CirclePoint<F>;
+    fn antipode(&self) -> CirclePoint<F>;
+    fn repeated_double(&self, n: u32) -> CirclePoint<F>;
+    fn log_order(&self) -> u32;
+    fn zeroes(shape: usize) -> Vec<CirclePoint<F>>;
+    fn inverse_x(&self) -> FieldElement<F>;
+    fn inverse_y(&self) -> FieldElement<F>;
+    fn to_extension<L: IsField>(&self) -> CirclePoint<L>
+    where
+        F: IsSubFieldOf<L>;
+}
+
+impl<F: IsField> CircleImpl<F> for CirclePoint<F> {
+    fn new(x: u32, y: u32) -> CirclePoint<F> {
+        CirclePoint {
+            x: FieldElement::from(x as u64),
+            y: FieldElement::from(y as u64),
+        }
+    }
+
+    fn new_with_field_elements(x: FieldElement<F>, y: FieldElement<F>) -> CirclePoint<F> {
+        CirclePoint { x, y }
     }
 
-    fn new_with_field_elements(x: FieldElement<M31>, y: FieldElement<M31>) -> CirclePoint {
-        return CirclePoint { x, y };
+    // stereographic projection from (-1, 0): t -> ((1-t^2)/(1+t^2), 2t/(1+t^2))
+    // every point except (-1, 0) is hit by exactly one t, so a uniformly drawn t
+    // (e.g. a QM31 challenge from the transcript) gives a uniform point on the circle over F
+    // panics if 1 + t^2 = 0, i.e. t = +-i, which only happens with negligible probability
+    fn from_stereographic(t: FieldElement<F>) -> CirclePoint<F> {
+        let t_squared = t.square();
+        let inv_denominator = (FieldElement::<F>::one() + &t_squared).inv().unwrap();
+        CirclePoint {
+            x: (FieldElement::<F>::one() - t_squared) * &inv_denominator,
+            y: t.double() * inv_denominator,
+        }
     }
 
-    fn get_x(&self) -> FieldElement<M31> {
-        return self.x;
+    fn get_x(&self) -> FieldElement<F> {
+        self.x.clone()
     }
 
-    fn get_y(&self) -> FieldElement<M31> {
-        return self.y;
+    fn get_y(&self) -> FieldElement<F> {
+        self.y.clone()
     }
 
     fn zero() -> Self {
         CirclePoint {
-            x: FieldElement::new(0),
-            y: FieldElement::new(0),
+            x: FieldElement::zero(),
+            y: FieldElement::zero(),
         }
     }
     // (x,y) ->  (2x^2-1 , 2*x*y)
-    fn double(&self) -> CirclePoint {
-        return CirclePoint {
+    fn double(&self) -> CirclePoint<F> {
+        CirclePoint {
             x: self.x.square().double() - FieldElement::one(),
-            y: (self.y * self.x).double(),
-        };
+            y: (&self.y * &self.x).double(),
+        }
     }
 
-    fn zeroes(shape: usize) -> Vec<CirclePoint> {
+    // (x,y) -> (x,-y), the inverse of the point in the circle group
+    fn conjugate(&self) -> CirclePoint<F> {
+        CirclePoint {
+            x: self.x.clone(),
+            y: -&self.y,
+        }
+    }
+
+    // (x,y) -> (-x,-y), i.e. P + (-1,0) where (-1,0) is the unique point of order 2
+    fn antipode(&self) -> CirclePoint<F> {
+        CirclePoint {
+            x: -&self.x,
+            y: -&self.y,
+        }
+    }
+
+    // 2^n * P
+    fn repeated_double(&self, n: u32) -> CirclePoint<F> {
+        let mut res = self.clone();
+        for _ in 0..n {
+            res = res.double();
+        }
+        res
+    }
+
+    // log2 of the order of the point, assuming the order is a power of 2
+    // (true for every point over M31, where the group has order 2^31)
+    // only x is tracked since (1,0) is the only point with x = 1
+    fn log_order(&self) -> u32 {
+        let mut res = 0;
+        let mut x = self.x.clone();
+        while x != FieldElement::one() {
+            x = x.square().double() - FieldElement::one();
+            res += 1;
+            assert!(res <= 64, "order of the point is not a power of 2");
+        }
+        res
+    }
+
+    fn zeroes(shape: usize) -> Vec<CirclePoint<F>> {
         vec![
             CirclePoint {
-                x: FieldElement::new(1), // ensure this
-                y: FieldElement::new(0)
+                x: FieldElement::one(), // ensure this
+                y: FieldElement::zero()
             };
             shape
         ]
     }
 
-    fn inverse_x(&self) -> FieldElement<M31> {
-        return FieldElement::inv(&self.x).unwrap();
+    fn inverse_x(&self) -> FieldElement<F> {
+        FieldElement::inv(&self.x).unwrap()
     }
 
-    fn inverse_y(&self) -> FieldElement<M31> {
-        return FieldElement::inv(&self.y).unwrap();
+    fn inverse_y(&self) -> FieldElement<F> {
+        FieldElement::inv(&self.y).unwrap()
+    }
+
+    fn to_extension<L: IsField>(&self) -> CirclePoint<L>
+    where
+        F: IsSubFieldOf<L>,
+    {
+        CirclePoint {
+            x: self.x.clone().to_extension(),
+            y: self.y.clone().to_extension(),
+        }
     }
 }
 
@@ -94,11 +203,12 @@ pub fn G() -> CirclePoint {
     }
 }
 
+// the identity of the circle group
 #[allow(non_snake_case)]
-pub fn Z() -> CirclePoint {
+pub fn Z<F: IsField>() -> CirclePoint<F> {
     CirclePoint {
-        x: FieldElement::new(1),
-        y: FieldElement::new(0),
+        x: FieldElement::one(),
+        y: FieldElement::zero(),
     }
 }
 
@@ -106,45 +216,92 @@ pub fn inverse<F>(x: FieldElement<F>) -> FieldElement<F>
 where
     F: IsField,
 {
-    return FieldElement::inv(&x).unwrap();
+    FieldElement::inv(&x).unwrap()
 }
 
-pub fn scalar_multiply(c: CirclePoint, n: u32) -> CirclePoint {
-    match n {
-        0 => CirclePoint::new(1, 0), // confirm if (0,0)
-        1 => c,
-        _ => {
-            let half_result = scalar_multiply(c.clone(), n / 2);
-            let doubled = half_result.double();
-            if n % 2 == 0 {
-                doubled
-            } else {
-                doubled.add(c)
-            }
+pub fn scalar_multiply<F: IsField>(c: CirclePoint<F>, n: u32) -> CirclePoint<F> {
+    c * (n as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qm31::{QM31Impl, QM31};
+    use crate::utils::random_values;
+
+    // deterministic pseudo random scalars, so the properties are checked on many points
+    fn scalars(n: usize) -> Vec<u64> {
+        random_values(n, 0x9e3779b97f4a7c15).iter().map(|v| v.representative() as u64).collect()
+    }
+
+    fn is_on_circle<F: IsField>(p: &CirclePoint<F>) -> bool {
+        p.get_x().square() + p.get_y().square() == FieldElement::one()
+    }
+
+    #[test]
+    fn generator_has_order_2_pow_31() {
+        assert!(is_on_circle(&G()));
+        assert_eq!(G().log_order(), 31);
+        assert_eq!(G().repeated_double(31), Z());
+        assert_eq!(G().repeated_double(30), CirclePoint::new(MODULUS - 1, 0));
+        assert_eq!(G() * (1 << 31), Z());
+    }
+
+    #[test]
+    fn group_axioms() {
+        let s = scalars(60);
+        for k in s.chunks(3) {
+            let (a, b, c) = (G() * k[0], G() * k[1], G() * k[2]);
+            assert!(is_on_circle(&a));
+            assert_eq!((a + b) + c, a + (b + c));
+            assert_eq!(a + b, b + a);
+            assert_eq!(a + Z(), a);
+            assert_eq!(a + (-a), Z());
+            assert_eq!(a - b, a + b.conjugate());
+            assert_eq!((a - b) + b, a);
+            assert_eq!(a.double(), a + a);
         }
     }
-}
 
-/// n==0 not handled
-pub fn scalar_division(c: CirclePoint, n: u32) -> CirclePoint {
-    let field_inverse_n: FieldElement<M31> = (FieldElement::new(n)).inv().unwrap();
-    return scalar_multiply(c, field_inverse_n.to_raw());
-}
+    #[test]
+    fn scalar_multiplication_is_consistent() {
+        let s = scalars(40);
+        for k in s.chunks(2) {
+            let (n, m) = (k[0], k[1]);
+            assert_eq!(G() * n + G() * m, G() * (n + m));
+            assert_eq!((G() * n) * m, G() * (n * m % (1 << 31)));
+            assert_eq!(G() * n, scalar_multiply(G(), n as u32));
+            assert_eq!(-(G() * n), G() * ((1 << 31) - n % (1 << 31)));
+        }
+        #[allow(clippy::erasing_op)]
+        let zero = G() * 0;
+        assert_eq!(zero, Z());
+        assert_eq!(G() * 1, G());
+    }
 
-// naive implementation for Div , Mul standard operations, rather  implement like `Add` as above
-pub fn div(c1: CirclePoint, c2: CirclePoint) -> CirclePoint {
-    let new_x = (c1.get_x().inv().unwrap().to_raw()).wrapping_mul(c2.get_x().to_raw());
-    let new_y = (c1.get_y().inv().unwrap().to_raw()).wrapping_mul(c2.get_y().to_raw());
-    return CirclePoint::new(new_x, new_y);
-}
+    #[test]
+    fn antipode_and_log_order() {
+        let half_turn = CirclePoint::new(MODULUS - 1, 0);
+        for n in scalars(20) {
+            let p = G() * n;
+            assert_eq!(p.antipode(), p + half_turn);
+            assert_eq!(p.antipode().antipode(), p);
+            assert_eq!(p.repeated_double(3), p * 8);
+        }
+        for log in 0..=31 {
+            assert_eq!(G().repeated_double(31 - log).log_order(), log);
+        }
+    }
 
-pub fn subtract(c1: CirclePoint, c2: CirclePoint) -> CirclePoint {
-    let new_x = c1.get_x().to_raw() - c2.get_x().to_raw() + MODULUS;
-    let new_y = c1.get_y().to_raw() - c2.get_y().to_raw() + MODULUS;
-    return <CirclePoint as CircleImpl>::new(new_x, new_y);
-}
-pub fn multiply(c1: CirclePoint, c2: CirclePoint) -> CirclePoint {
-    let new_x = (c1.get_x().to_raw()).wrapping_mul(c2.get_x().to_raw());
-    let new_y = (c1.get_y().to_raw()).wrapping_mul(c2.get_y().to_raw());
-    return CirclePoint::new(new_x, new_y);
+    #[test]
+    fn extension_points_follow_the_same_law() {
+        let t = FieldElement::<QM31>::from_u32s(7, 11, 13, 17);
+        let p = CirclePoint::<QM31>::from_stereographic(t);
+        let g: CirclePoint<QM31> = G().to_extension();
+        assert!(is_on_circle(&p));
+        assert!(is_on_circle(&(p + g)));
+        assert_eq!((p + g) - g, p);
+        assert_eq!(p + (-p), Z());
+        assert_eq!(p * 5, p.double().double() + p);
+    }
 }
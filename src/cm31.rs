@@ -0,0 +1,188 @@
+use lambdaworks_math::field::element::FieldElement;
+use lambdaworks_math::field::errors::FieldError;
+use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use lambdaworks_math::field::traits::{IsField, IsSubFieldOf};
+
+// complex extension of M31: a + b*i with i^2 = -1
+// -1 is a non residue since p = 3 mod 4, so this is a field of size p^2
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
+pub struct CM31;
+
+impl IsField for CM31 {
+    // [real, imaginary]
+    type BaseType = [FieldElement<M31>; 2];
+
+    fn add(a: &Self::BaseType, b: &Self::BaseType) -> Self::BaseType {
+        [a[0] + b[0], a[1] + b[1]]
+    }
+
+    // (a0 + a1*i)(b0 + b1*i) = a0*b0 - a1*b1 + (a0*b1 + a1*b0)*i
+    fn mul(a: &Self::BaseType, b: &Self::BaseType) -> Self::BaseType {
+        let a0b0 = a[0] * b[0];
+        let a1b1 = a[1] * b[1];
+        let cross = (a[0] + a[1]) * (b[0] + b[1]);
+        [a0b0 - a1b1, cross - a0b0 - a1b1]
+    }
+
+    fn square(a: &Self::BaseType) -> Self::BaseType {
+        [(a[0] + a[1]) * (a[0] - a[1]), (a[0] * a[1]).double()]
+    }
+
+    fn sub(a: &Self::BaseType, b: &Self::BaseType) -> Self::BaseType {
+        [a[0] - b[0], a[1] - b[1]]
+    }
+
+    fn neg(a: &Self::BaseType) -> Self::BaseType {
+        [-a[0], -a[1]]
+    }
+
+    // 1/(a + b*i) = (a - b*i)/(a^2 + b^2)
+    fn inv(a: &Self::BaseType) -> Result<Self::BaseType, FieldError> {
+        let norm_inv = (a[0].square() + a[1].square()).inv()?;
+        Ok([a[0] * norm_inv, -a[1] * norm_inv])
+    }
+
+    fn div(a: &Self::BaseType, b: &Self::BaseType) -> Self::BaseType {
+        <Self as IsField>::mul(a, &Self::inv(b).unwrap())
+    }
+
+    fn eq(a: &Self::BaseType, b: &Self::BaseType) -> bool {
+        a[0] == b[0] && a[1] == b[1]
+    }
+
+    fn zero() -> Self::BaseType {
+        [FieldElement::zero(), FieldElement::zero()]
+    }
+
+    fn one() -> Self::BaseType {
+        [FieldElement::one(), FieldElement::zero()]
+    }
+
+    fn from_u64(x: u64) -> Self::BaseType {
+        [FieldElement::from(x), FieldElement::zero()]
+    }
+
+    fn from_base_type(x: Self::BaseType) -> Self::BaseType {
+        x
+    }
+}
+
+// lets FieldElement<M31> * FieldElement<CM31> (and +, -, /) work directly
+impl IsSubFieldOf<CM31> for M31 {
+    fn mul(a: &u32, b: &[FieldElement<M31>; 2]) -> [FieldElement<M31>; 2] {
+        let a = FieldElement::<M31>::from_raw(*a);
+        [a * b[0], a * b[1]]
+    }
+
+    fn add(a: &u32, b: &[FieldElement<M31>; 2]) -> [FieldElement<M31>; 2] {
+        [FieldElement::<M31>::from_raw(*a) + b[0], b[1]]
+    }
+
+    fn div(a: &u32, b: &[FieldElement<M31>; 2]) -> [FieldElement<M31>; 2] {
+        let b_inv = CM31::inv(b).unwrap();
+        <M31 as IsSubFieldOf<CM31>>::mul(a, &b_inv)
+    }
+
+    fn sub(a: &u32, b: &[FieldElement<M31>; 2]) -> [FieldElement<M31>; 2] {
+        [FieldElement::<M31>::from_raw(*a) - b[0], -b[1]]
+    }
+
+    fn embed(a: u32) -> [FieldElement<M31>; 2] {
+        [FieldElement::from_raw(a), FieldElement::zero()]
+    }
+
+    fn to_subfield_vec(b: [FieldElement<M31>; 2]) -> Vec<u32> {
+        b.iter().map(|x| x.to_raw()).collect()
+    }
+}
+
+pub trait CM31Impl {
+    fn from_m31(real: FieldElement<M31>, imaginary: FieldElement<M31>) -> Self;
+    fn from_u32s(real: u32, imaginary: u32) -> Self;
+    fn real(&self) -> FieldElement<M31>;
+    fn imaginary(&self) -> FieldElement<M31>;
+    fn conjugate(&self) -> Self;
+}
+
+impl CM31Impl for FieldElement<CM31> {
+    fn from_m31(real: FieldElement<M31>, imaginary: FieldElement<M31>) -> Self {
+        FieldElement::from_raw([real, imaginary])
+    }
+
+    fn from_u32s(real: u32, imaginary: u32) -> Self {
+        FieldElement::from_raw([FieldElement::new(real), FieldElement::new(imaginary)])
+    }
+
+    fn real(&self) -> FieldElement<M31> {
+        self.value()[0]
+    }
+
+    fn imaginary(&self) -> FieldElement<M31> {
+        self.value()[1]
+    }
+
+    // also the frobenius map x -> x^p, since i^p = -i
+    fn conjugate(&self) -> Self {
+        FieldElement::from_raw([self.value()[0], -self.value()[1]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circle::MODULUS;
+    use crate::utils::{check_field_axioms, random_values};
+
+    fn random_cm31s(n: usize, seed: u64) -> Vec<FieldElement<CM31>> {
+        random_values(2 * n, seed)
+            .chunks(2)
+            .map(|c| FieldElement::<CM31>::from_m31(c[0], c[1]))
+            .collect()
+    }
+
+    #[test]
+    fn is_a_field() {
+        let mut values = random_cm31s(50, 1);
+        // 0, 1, i and p - 1 in either coordinate, between random values
+        for (k, (a, b)) in [(0, 0), (1, 0), (0, 1), (MODULUS - 1, 0), (0, MODULUS - 1)].into_iter().enumerate() {
+            values.insert(3 * k + 1, FieldElement::<CM31>::from_u32s(a, b));
+        }
+        check_field_axioms(&values);
+
+        let i = FieldElement::<CM31>::from_u32s(0, 1);
+        assert_eq!(i.square(), -FieldElement::<CM31>::one());
+    }
+
+    #[test]
+    fn inverts_every_nonzero_value() {
+        for x in random_cm31s(50, 2) {
+            assert_eq!(x * x.inv().unwrap(), FieldElement::one());
+        }
+        // the norm a^2 + b^2 of a nonzero value is never 0, -1 not being a square
+        assert_eq!(FieldElement::<CM31>::from_u32s(0, 1).inv().unwrap(), FieldElement::from_u32s(0, MODULUS - 1));
+        assert!(matches!(FieldElement::<CM31>::zero().inv(), Err(FieldError::InvZeroError)));
+    }
+
+    #[test]
+    fn conjugate_is_the_frobenius() {
+        for x in random_cm31s(20, 3) {
+            assert_eq!(x.conjugate(), x.pow(MODULUS));
+            assert_ne!(x.conjugate(), x);
+            assert_eq!(x.conjugate().conjugate(), x);
+        }
+        let real = FieldElement::<CM31>::from_u32s(12345, 0);
+        assert_eq!(real.conjugate(), real);
+    }
+
+    #[test]
+    fn mixed_operations_match_the_extension() {
+        let m31s = random_values(20, 4);
+        for (a, b) in m31s.iter().zip(random_cm31s(20, 5)) {
+            let a_ext = a.to_extension::<CM31>();
+            assert_eq!(a * b, a_ext * b);
+            assert_eq!(a + b, a_ext + b);
+            assert_eq!(a - b, a_ext - b);
+            assert_eq!(a / b, a_ext / b);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use crate::circle::{CircleImpl, CirclePoint, G, Z};
+use lambdaworks_math::field::element::FieldElement;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// the circle group over M31 is cyclic of order p + 1 = 2^31, generated by G()
+pub const M31_CIRCLE_LOG_ORDER: u32 = 31;
+
+// the point k*G, stored as k mod 2^31
+// domains are described by indices so that shifting, doubling and locating points
+// is integer arithmetic instead of group operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CirclePointIndex(pub usize);
+
+impl CirclePointIndex {
+    pub fn zero() -> Self {
+        CirclePointIndex(0)
+    }
+
+    // generator of the subgroup of size 2^log_size
+    pub fn subgroup_gen(log_size: u32) -> Self {
+        assert!(log_size <= M31_CIRCLE_LOG_ORDER);
+        CirclePointIndex(1 << (M31_CIRCLE_LOG_ORDER - log_size)).reduce()
+    }
+
+    pub fn to_point(self) -> CirclePoint {
+        G() * self.0 as u64
+    }
+
+    // inverse of to_point, panics on a point that is not on the circle
+    pub fn from_point(point: &CirclePoint) -> Self {
+        Self::try_from_point(point).expect("point is not on the circle")
+    }
+
+    // inverse of to_point, None for a point that is not on the circle. The group is a
+    // 2-group, so the discrete log can be read bit by bit: after removing the lower j bits,
+    // doubling 30-j times lands on Z() if bit j is 0 and on (-1,0) if it is 1
+    pub fn try_from_point(point: &CirclePoint) -> Option<Self> {
+        if point.get_x().square() + point.get_y().square() != FieldElement::one() {
+            return None;
+        }
+        let mut rest = *point;
+        let mut index = 0;
+        for j in 0..M31_CIRCLE_LOG_ORDER {
+            if rest.repeated_double(M31_CIRCLE_LOG_ORDER - 1 - j) != Z() {
+                rest = rest - G().repeated_double(j);
+                index |= 1 << j;
+            }
+        }
+        (rest == Z()).then_some(CirclePointIndex(index))
+    }
+
+    pub fn half(self) -> Self {
+        assert!(self.0 & 1 == 0, "index is odd");
+        CirclePointIndex(self.0 >> 1)
+    }
+
+    fn reduce(self) -> Self {
+        CirclePointIndex(self.0 & ((1 << M31_CIRCLE_LOG_ORDER) - 1))
+    }
+}
+
+impl Add for CirclePointIndex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        CirclePointIndex(self.0 + rhs.0).reduce()
+    }
+}
+
+impl Sub for CirclePointIndex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        CirclePointIndex(self.0 + (1 << M31_CIRCLE_LOG_ORDER) - rhs.0).reduce()
+    }
+}
+
+impl Mul<usize> for CirclePointIndex {
+    type Output = Self;
+    fn mul(self, rhs: usize) -> Self::Output {
+        CirclePointIndex(self.0.wrapping_mul(rhs)).reduce()
+    }
+}
+
+// index of the conjugate point
+impl Neg for CirclePointIndex {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        CirclePointIndex((1 << M31_CIRCLE_LOG_ORDER) - self.0).reduce()
+    }
+}
+
+// initial + i*step for i in 0..2^log_size, where step generates the subgroup of size 2^log_size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coset {
+    initial_index: CirclePointIndex,
+    initial: CirclePoint,
+    step_size: CirclePointIndex,
+    step: CirclePoint,
+    log_size: u32,
+}
+
+impl Coset {
+    pub fn new(initial_index: CirclePointIndex, log_size: u32) -> Self {
+        let step_size = CirclePointIndex::subgroup_gen(log_size);
+        Coset {
+            initial_index,
+            initial: initial_index.to_point(),
+            step_size,
+            step: step_size.to_point(),
+            log_size,
+        }
+    }
+
+    // the subgroup of size 2^log_size itself
+    pub fn subgroup(log_size: u32) -> Self {
+        Coset::new(CirclePointIndex::zero(), log_size)
+    }
+
+    // odd multiples of the generator of the subgroup of size 2^(log_size+1)
+    pub fn odds(log_size: u32) -> Self {
+        Coset::new(CirclePointIndex::subgroup_gen(log_size + 1), log_size)
+    }
+
+    pub fn size(&self) -> usize {
+        1 << self.log_size
+    }
+
+    pub fn log_size(&self) -> u32 {
+        self.log_size
+    }
+
+    pub fn initial(&self) -> CirclePoint {
+        self.initial
+    }
+
+    pub fn step(&self) -> CirclePoint {
+        self.step
+    }
+
+    pub fn initial_index(&self) -> CirclePointIndex {
+        self.initial_index
+    }
+
+    pub fn step_size(&self) -> CirclePointIndex {
+        self.step_size
+    }
+
+    pub fn index_at(&self, i: usize) -> CirclePointIndex {
+        self.initial_index + self.step_size * i
+    }
+
+    pub fn at(&self, i: usize) -> CirclePoint {
+        self.index_at(i).to_point()
+    }
+
+    // position of point in the coset, None if it's not in it
+    pub fn index_of(&self, point: &CirclePoint) -> Option<usize> {
+        let offset = CirclePointIndex::try_from_point(point)? - self.initial_index;
+        if self.log_size == 0 {
+            return (offset.0 == 0).then_some(0);
+        }
+        if !offset.0.is_multiple_of(self.step_size.0) {
+            return None;
+        }
+        Some(offset.0 / self.step_size.0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = CirclePoint> {
+        let step = self.step;
+        std::iter::successors(Some(self.initial), move |p| Some(*p + step)).take(self.size())
+    }
+
+    // {2p : p in coset}, a coset of half the size
+    pub fn double(&self) -> Self {
+        assert!(self.log_size > 0);
+        Coset::new(self.initial_index * 2, self.log_size - 1)
+    }
+
+    // {-p : p in coset}, keeping the order so that conjugate().at(i) == -at(i)
+    pub fn conjugate(&self) -> Self {
+        Coset {
+            initial_index: -self.initial_index,
+            initial: -self.initial,
+            step_size: -self.step_size,
+            step: -self.step,
+            log_size: self.log_size,
+        }
+    }
+
+    pub fn shift(&self, by: CirclePointIndex) -> Self {
+        let initial_index = self.initial_index + by;
+        Coset {
+            initial_index,
+            initial: initial_index.to_point(),
+            ..*self
+        }
+    }
+}
+
+// the coset of odd multiples of the generator of order 2^(log_size+1)
+// this is the domain the trace lives on; its conjugate is itself,
+// so it splits into a half coset and that half's conjugate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CanonicCoset {
+    coset: Coset,
+}
+
+impl CanonicCoset {
+    pub fn new(log_size: u32) -> Self {
+        assert!(log_size > 0);
+        CanonicCoset {
+            coset: Coset::odds(log_size),
+        }
+    }
+
+    pub fn coset(&self) -> Coset {
+        self.coset
+    }
+
+    pub fn log_size(&self) -> u32 {
+        self.coset.log_size
+    }
+
+    pub fn size(&self) -> usize {
+        self.coset.size()
+    }
+
+    pub fn initial(&self) -> CirclePoint {
+        self.coset.initial
+    }
+
+    pub fn step(&self) -> CirclePoint {
+        self.coset.step
+    }
+
+    pub fn at(&self, i: usize) -> CirclePoint {
+        self.coset.at(i)
+    }
+
+    pub fn index_of(&self, point: &CirclePoint) -> Option<usize> {
+        self.coset.index_of(point)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = CirclePoint> {
+        self.coset.iter()
+    }
+
+    pub fn shift(&self, by: CirclePointIndex) -> Coset {
+        self.coset.shift(by)
+    }
+
+    // the even positions of the coset, whose conjugates are exactly the odd positions
+    pub fn half_coset(&self) -> Coset {
+        Coset::new(self.coset.initial_index, self.log_size() - 1)
+    }
+
+    pub fn circle_domain(&self) -> CircleDomain {
+        CircleDomain::new(self.half_coset())
+    }
+}
+
+// twin coset: half_coset together with its conjugate
+// points are ordered as half_coset followed by the conjugates in the same order,
+// so at(i) and at(i + size/2) are conjugate pairs (x,y), (x,-y)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CircleDomain {
+    half_coset: Coset,
+}
+
+impl CircleDomain {
+    pub fn new(half_coset: Coset) -> Self {
+        CircleDomain { half_coset }
+    }
+
+    pub fn half_coset(&self) -> Coset {
+        self.half_coset
+    }
+
+    pub fn log_size(&self) -> u32 {
+        self.half_coset.log_size + 1
+    }
+
+    pub fn size(&self) -> usize {
+        1 << self.log_size()
+    }
+
+    pub fn index_at(&self, i: usize) -> CirclePointIndex {
+        let half_size = self.half_coset.size();
+        if i < half_size {
+            self.half_coset.index_at(i)
+        } else {
+            -self.half_coset.index_at(i - half_size)
+        }
+    }
+
+    pub fn at(&self, i: usize) -> CirclePoint {
+        self.index_at(i).to_point()
+    }
+
+    pub fn index_of(&self, point: &CirclePoint) -> Option<usize> {
+        if let Some(i) = self.half_coset.index_of(point) {
+            return Some(i);
+        }
+        self.half_coset
+            .index_of(&point.conjugate())
+            .map(|i| i + self.half_coset.size())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = CirclePoint> {
+        self.half_coset
+            .iter()
+            .chain(self.half_coset.conjugate().iter())
+    }
+
+    // same set of points with the two halves swapped
+    pub fn conjugate(&self) -> Self {
+        CircleDomain::new(self.half_coset.conjugate())
+    }
+
+    // the domain of the half coset shifted by `by`: at(i) moves by by in the first half and,
+    // being its conjugate, by -by in the second
+    pub fn shift(&self, by: CirclePointIndex) -> Self {
+        CircleDomain::new(self.half_coset.shift(by))
+    }
+
+    // whether this is the circle domain of a canonic coset (the domain a trace is interpolated on)
+    pub fn is_canonic(&self) -> bool {
+        self.half_coset.initial_index * 4 == self.half_coset.step_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices_and_points_round_trip() {
+        for k in [0, 1, 2, 12345, (1 << 30) - 1, 1 << 30, (1 << 31) - 1] {
+            let index = CirclePointIndex(k);
+            assert_eq!(CirclePointIndex::from_point(&index.to_point()), index);
+        }
+        assert_eq!(CirclePointIndex::zero().to_point(), Z());
+        let (a, b) = (CirclePointIndex(5), CirclePointIndex(1 << 30));
+        assert_eq!((a + b).to_point(), a.to_point() + b.to_point());
+        assert_eq!((a - b).to_point(), a.to_point() - b.to_point());
+        assert_eq!((a * 12).to_point(), a.to_point() * 12);
+        assert_eq!((-a).to_point(), a.to_point().conjugate());
+        for log_size in 0..=M31_CIRCLE_LOG_ORDER {
+            assert_eq!(CirclePointIndex::subgroup_gen(log_size).to_point().log_order(), log_size);
+        }
+    }
+
+    #[test]
+    fn cosets_locate_their_points() {
+        let coset = Coset::new(CirclePointIndex(12345), 5);
+        let points: Vec<CirclePoint> = coset.iter().collect();
+        assert_eq!(points.len(), coset.size());
+        for (i, p) in points.iter().enumerate() {
+            assert_eq!(coset.at(i), *p);
+            assert_eq!(coset.index_of(p), Some(i));
+        }
+        assert_eq!(coset.index_of(&(points[0] + G())), None);
+        let single = Coset::new(CirclePointIndex(7), 0);
+        assert_eq!(single.index_of(&single.at(0)), Some(0));
+        assert_eq!(single.index_of(&G()), None);
+
+        let by = CirclePointIndex(99);
+        assert!(coset.double().iter().zip(&points).all(|(d, p)| d == p.double()));
+        assert!(coset.conjugate().iter().zip(&points).all(|(c, p)| c == p.conjugate()));
+        assert!(coset.shift(by).iter().zip(&points).all(|(s, p)| s == *p + by.to_point()));
+    }
+
+    #[test]
+    fn canonic_cosets_split_into_conjugate_halves() {
+        for log_size in 1..=6 {
+            let canonic = CanonicCoset::new(log_size);
+            let points: Vec<CirclePoint> = canonic.iter().collect();
+            for (i, p) in points.iter().enumerate() {
+                assert_eq!(canonic.at(i), *p);
+                assert_eq!(canonic.index_of(p), Some(i));
+                // a canonic coset is its own conjugate
+                assert!(canonic.index_of(&p.conjugate()).is_some());
+            }
+            for (i, p) in canonic.half_coset().iter().enumerate() {
+                assert_eq!(p, points[2 * i]);
+            }
+
+            let domain = canonic.circle_domain();
+            assert!(domain.is_canonic());
+            assert_eq!(domain.size(), canonic.size());
+            let half = domain.size() / 2;
+            for (i, p) in domain.iter().enumerate() {
+                assert_eq!(domain.at(i), p);
+                assert_eq!(domain.index_of(&p), Some(i));
+                assert!(canonic.index_of(&p).is_some());
+                if i < half {
+                    assert_eq!(domain.at(i + half), p.conjugate());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn circle_domains_conjugate_and_shift() {
+        let domain = CanonicCoset::new(5).circle_domain();
+        let (size, half) = (domain.size(), domain.size() / 2);
+        let conjugate = domain.conjugate();
+        for i in 0..size {
+            assert_eq!(conjugate.at(i), domain.at((i + half) % size));
+        }
+
+        let by = CirclePointIndex(3);
+        let shifted = domain.shift(by);
+        assert!(!shifted.is_canonic());
+        for i in 0..half {
+            assert_eq!(shifted.at(i), domain.at(i) + by.to_point());
+            assert_eq!(shifted.at(i + half), domain.at(i + half) - by.to_point());
+        }
+        for (i, p) in shifted.iter().enumerate() {
+            assert_eq!(shifted.index_of(&p), Some(i));
+            assert_eq!(domain.index_of(&p), None);
+        }
+        assert!(!CircleDomain::new(Coset::new(CirclePointIndex(12345), 4)).is_canonic());
+    }
+
+    #[test]
+    fn points_off_the_circle_have_no_index() {
+        let off = CirclePoint::new(3, 5);
+        assert_eq!(CirclePointIndex::try_from_point(&off), None);
+        assert_eq!(Coset::new(CirclePointIndex(12345), 5).index_of(&off), None);
+        assert_eq!(CanonicCoset::new(5).index_of(&off), None);
+        assert_eq!(CanonicCoset::new(5).circle_domain().index_of(&off), None);
+        let index = CirclePointIndex(12345);
+        assert_eq!(CirclePointIndex::try_from_point(&index.to_point()), Some(index));
+    }
+}
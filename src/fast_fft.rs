@@ -1,164 +1,238 @@
-use crate::circle::CirclePoint;
-use crate::utils::*;
-use ndarray::{s, Ix1};
-use ndarray::{Array, ArrayView, Axis, Dimension};
-
-// yet to fix new functions with ndarray and reverse bit order
-
-//list of evals to list of coeffs
-// here they are in a weird order
-// 1, y ,x , xy, 2x^2-1
-pub fn fft<D, T>(
-    vals: &Array<T, D>,
-    is_top_level: bool,
-    rbos: &[usize],
-    invx: &[T],
-    invy: &[T],
-) -> Array<T, D>
-where
-    D: Dimension,
-    T: Clone,
+use crate::circle::{CircleImpl, CirclePoint};
+use crate::packed;
+use crate::precomputes::TwiddleTree;
+use lambdaworks_math::field::element::FieldElement;
+use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use lambdaworks_math::field::traits::{IsField, IsSubFieldOf};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::any::TypeId;
+
+// in place, iterative versions of fft::fft and fft::inv_fft
+// evaluations are in bit reversed order of the twiddle tree's domain, coefficients are in
+// the natural order of the circle basis 1, y, x, xy, 2x^2-1, ... (same basis as fft.rs)
+// no layer allocates: every layer is a pass of butterflies over vals
+// over M31 the butterflies run on PackedM31 lanes (see packed.rs)
+
+// with the parallel feature, layers of at least this many values are split between threads
+#[cfg(feature = "parallel")]
+const MIN_PARALLEL_LOG_SIZE: u32 = 12;
+
+// applies f to every block of a layer: vals is cut into blocks of size 2*half, f gets the two
+// halves of a block, whose butterflies are (left[j], right[j]), and the block's twiddle
+fn for_each_butterfly<F: IsField, B>(
+    vals: &mut [FieldElement<F>],
+    half: usize,
+    twiddles: &[FieldElement<M31>],
+    f: B,
+) where
+    FieldElement<F>: Send,
+    B: Fn(&mut [FieldElement<F>], &mut [FieldElement<F>], &FieldElement<M31>) + Sync,
 {
-    let mut vals = vals.to_owned();
-    let size = vals.len_of(Axis(0));
-    let log_size = log2(size as u32);
-
-    for i in 0..log_size {
-        let sub_blocks = 1 << i;
-        let block_size = size >> i;
-
-        vals.to_shape((sub_blocks, block_size)).unwrap();
-        let full_len = block_size;
-        let half_len = full_len >> 1;
-
-        let l = vals.slice(s![.., ..half_len]).to_owned();
-        let mut r = vals.slice(s![.., half_len..]).to_owned();
-        r.flip_axis(Axis(1));
-
-        let f0 = &l + &r;
-
-        // Select twiddle factors based on level and top-level flag
-        let twiddle = if i == 0 && is_top_level {
-            ArrayView::from(&invy[full_len..full_len + half_len])
+    #[cfg(feature = "parallel")]
+    if vals.len() >= 1 << MIN_PARALLEL_LOG_SIZE {
+        if half < 1 << (MIN_PARALLEL_LOG_SIZE - 1) {
+            // many small blocks: one block per task
+            vals.par_chunks_exact_mut(2 * half)
+                .zip(twiddles.par_iter())
+                .for_each(|(block, t)| {
+                    let (left, right) = block.split_at_mut(half);
+                    f(left, right, t);
+                });
         } else {
-            ArrayView::from(&invx[full_len * 2..full_len * 2 + half_len])
-        };
+            // few large blocks: split the butterflies of each block
+            let task_size = 1 << (MIN_PARALLEL_LOG_SIZE - 1);
+            for (block, t) in vals.chunks_exact_mut(2 * half).zip(twiddles) {
+                let (left, right) = block.split_at_mut(half);
+                left.par_chunks_mut(task_size)
+                    .zip(right.par_chunks_mut(task_size))
+                    .for_each(|(left, right)| f(left, right, t));
+            }
+        }
+        return;
+    }
 
-        // Reshape twiddle for broadcasting
-        let twiddle_box = twiddle.into_shape((1, half_len)).unwrap();
+    for (block, t) in vals.chunks_exact_mut(2 * half).zip(twiddles) {
+        let (left, right) = block.split_at_mut(half);
+        f(left, right, t);
+    }
+}
 
-        let f1 = (&l - &r) * &twiddle_box;
+// vals as M31 values when F is M31, so that the packed kernels can run on them
+fn as_m31<F: IsField + 'static>(vals: &mut [FieldElement<F>]) -> Option<&mut [FieldElement<M31>]> {
+    if TypeId::of::<F>() != TypeId::of::<M31>() {
+        return None;
+    }
+    // SAFETY: F is M31, so both are the same slice type
+    Some(unsafe { &mut *(vals as *mut [FieldElement<F>] as *mut [FieldElement<M31>]) })
+}
 
-        // Update the original array
-        vals.slice_mut(s![.., ..half_len]).assign(&f0);
-        vals.slice_mut(s![.., half_len..]).assign(&f1);
+// (a, b) -> (a + b, (a - b)/t) for the pairs of a block, the inverse of butterflies up to a
+// factor 2
+fn ibutterflies<F: IsField + 'static>(
+    left: &mut [FieldElement<F>],
+    right: &mut [FieldElement<F>],
+    inv_t: &FieldElement<M31>,
+) where
+    M31: IsSubFieldOf<F>,
+{
+    if left.len() >= packed::LANES {
+        if let (Some(left), Some(right)) = (as_m31(left), as_m31(right)) {
+            return packed::ibutterflies(left, right, inv_t);
+        }
+    }
+    for (a, b) in left.iter_mut().zip(right) {
+        let diff = &*a - &*b;
+        *a = &*a + &*b;
+        *b = inv_t * diff;
     }
+}
 
-    // Reshape and apply reverse bit order, then divide by size
-    let reshaped = vals.into_shape(size).unwrap();
-    let reordered = Array::from_shape_vec(
-        size,
-        rbos[size..size * 2]
-            .iter()
-            .map(|&idx| reshaped[idx])
-            .collect(),
-    )
-    .unwrap();
-
-    reordered / T::from(size).unwrap()
+// (a, b) -> (a + t*b, a - t*b)
+fn butterflies<F: IsField + 'static>(
+    left: &mut [FieldElement<F>],
+    right: &mut [FieldElement<F>],
+    t: &FieldElement<M31>,
+) where
+    M31: IsSubFieldOf<F>,
+{
+    if left.len() >= packed::LANES {
+        if let (Some(left), Some(right)) = (as_m31(left), as_m31(right)) {
+            return packed::butterflies(left, right, t);
+        }
+    }
+    for (a, b) in left.iter_mut().zip(right) {
+        let tb = t * &*b;
+        *b = &*a - &tb;
+        *a = &*a + tb;
+    }
 }
 
-pub fn inv_fft<D, T>(vals: &Array<T, D>, sub_domains: &[CirclePoint], rbos: &[usize]) -> Array<T, D>
+// evaluations -> coefficients
+// vals holds the evaluations over twiddles.domain() in bit reversed order
+pub fn fft<F: IsField + 'static>(vals: &mut [FieldElement<F>], twiddles: &TwiddleTree)
 where
-    D: Dimension,
-    T: Clone,
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
 {
-    let mut vals = reverse_bit_order(vals);
-    let size = vals.len_of(Axis(0));
-    let log_size = log2(size as u32);
-
-    for i in (0..log_size).rev() {
-        let sub_blocks = 1 << i;
-        let block_size = size >> i;
-
-        vals.to_shape((sub_blocks, block_size)).unwrap();
-        let full_len = block_size;
-        let half_len = full_len >> 1;
-
-        let f0 = vals.slice(s![.., ..half_len]).to_owned();
-        let f1 = vals.slice(s![.., half_len..]).to_owned();
-
-        // Select twiddle factors based on level
-        let twiddle = if i == 0 {
-            ArrayView::from(
-                &sub_domains[full_len..full_len + half_len]
-                    .iter()
-                    .map(|p| p.y)
-                    .collect::<Vec<_>>(),
-            )
-        } else {
-            ArrayView::from(
-                &sub_domains[full_len * 2..full_len * 2 + half_len]
-                    .iter()
-                    .map(|p| p.x)
-                    .collect::<Vec<_>>(),
-            )
-        };
-
-        let f1_times_twiddle = &f1 * &twiddle.to_shape((1, half_len)).unwrap();
-
-        let l = &f0 + &f1_times_twiddle;
-        let r = &f0 - &f1_times_twiddle;
+    assert_eq!(vals.len(), twiddles.domain().size());
+    let log_size = twiddles.log_size();
 
-        vals.slice_mut(s![.., ..half_len]).assign(&l);
+    // circle layer: f(x,y) = f0(x) + y*f1(x)
+    for_each_butterfly(vals, 1, twiddles.inv_y_twiddles(), ibutterflies);
 
-        let mut r_flipped = r.to_owned();
-        r_flipped.flip_axis(Axis(1));
-        vals.slice_mut(s![.., half_len..]).assign(&r_flipped);
+    // line layers: g(x) = g0(2x^2-1) + x*g1(2x^2-1)
+    for layer in 1..log_size {
+        for_each_butterfly(vals, 1 << layer, twiddles.inv_x_twiddles(layer), ibutterflies);
     }
 
-    vals.to_shape(size).unwrap()
+    // every layer skipped the division by 2, do all of them at once
+    let inv_size = FieldElement::<M31>::from(vals.len() as u64).inv().unwrap();
+    #[cfg(feature = "parallel")]
+    let iter = vals.par_iter_mut();
+    #[cfg(not(feature = "parallel"))]
+    let iter = vals.iter_mut();
+    iter.for_each(|v| *v = inv_size * &*v);
 }
 
-fn bary_eval<T>(vals: &Array<T, Ix1>, pt: &CirclePoint, invx: &[T], invy: &[T]) -> T
+// coefficients -> evaluations
+// vals ends up holding the evaluations over twiddles.domain() in bit reversed order
+pub fn inv_fft<F: IsField + 'static>(vals: &mut [FieldElement<F>], twiddles: &TwiddleTree)
 where
-    T: Clone,
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
 {
-    let size = vals.len();
-    let log_size = log2(size);
-    let mut vals = vals.to_owned();
-
-    let mut baryfac = pt.y;
-    let mut current_level = 0;
-
-    for i in 0..log_size {
-        let full_len = vals.len();
-        let half_len = full_len >> 1;
+    assert_eq!(vals.len(), twiddles.domain().size());
+    let log_size = twiddles.log_size();
 
-        let l = vals[..half_len].to_owned();
-        let mut r = vals[half_len..].to_owned();
-        r.flip();
-
-        let f0 = &l + &r;
+    for layer in (1..log_size).rev() {
+        for_each_butterfly(vals, 1 << layer, twiddles.x_twiddles(layer), butterflies);
+    }
+    for_each_butterfly(vals, 1, twiddles.y_twiddles(), butterflies);
+}
 
-        let twiddle = if current_level == 0 {
-            ArrayView::from(&invy[full_len..full_len + half_len])
-        } else {
-            ArrayView::from(&invx[full_len * 2..full_len * 2 + half_len])
-        };
+// evaluations -> coefficients of a polynomial in x alone (basis 1, x, 2x^2-1, x(2x^2-1), ...,
+// the k-th of degree k), given on the line of the x of twiddles.domain()'s half coset, in
+// bit reversed order. These are the values FRI ends with, after the circle fold
+pub fn line_fft<F: IsField + 'static>(vals: &mut [FieldElement<F>], twiddles: &TwiddleTree)
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    assert_eq!(vals.len(), twiddles.domain().size() / 2);
+    for layer in 1..twiddles.log_size() {
+        for_each_butterfly(vals, 1 << (layer - 1), twiddles.inv_x_twiddles(layer), ibutterflies);
+    }
+    let inv_size = FieldElement::<M31>::from(vals.len() as u64).inv().unwrap();
+    vals.iter_mut().for_each(|v| *v = inv_size * &*v);
+}
 
-        if current_level == 1 {
-            baryfac = pt.x;
-        } else if current_level > 1 {
-            baryfac = baryfac * baryfac * T::from(2).unwrap() - T::from(1).unwrap();
+// value at point of the polynomial whose evaluations over twiddles.domain() are vals, in bit
+// reversed order. Runs the layers of fft but folds every pair with the coordinates of point
+// (y, then x, 2x^2-1, ...) instead of splitting it, so it costs n/2 + n/4 + ... + 1 steps and
+// no interpolation. point can be over an extension field, e.g. an out of domain sample
+pub fn bary_eval<F: IsSubFieldOf<E>, E: IsField>(
+    vals: &[FieldElement<F>],
+    point: &CirclePoint<E>,
+    twiddles: &TwiddleTree,
+) -> FieldElement<E>
+where
+    M31: IsSubFieldOf<F> + IsSubFieldOf<E>,
+{
+    assert_eq!(vals.len(), twiddles.domain().size());
+    let log_size = twiddles.log_size();
+
+    // circle layer: f0(x) + y*f1(x), each side is twice its value
+    let y = point.get_y();
+    let mut folded: Vec<FieldElement<E>> = vals
+        .chunks_exact(2)
+        .zip(twiddles.inv_y_twiddles())
+        .map(|(pair, inv_y)| {
+            let f1 = inv_y * (&pair[0] - &pair[1]);
+            &pair[0] + &pair[1] + f1 * &y
+        })
+        .collect();
+
+    // line layers: g0 + x*g1, folding in place
+    let mut x = point.get_x();
+    for layer in 1..log_size {
+        let half = folded.len() / 2;
+        for (h, inv_x) in twiddles.inv_x_twiddles(layer).iter().enumerate() {
+            let (a, b) = (&folded[2 * h], &folded[2 * h + 1]);
+            let g1 = inv_x * (a - b);
+            let g = a + b + g1 * &x;
+            folded[h] = g;
         }
+        folded.truncate(half);
+        x = x.square().double() - FieldElement::one();
+    }
 
-        let twiddle_box = twiddle.into_shape((half_len, 1)).unwrap();
-        let f1 = (&l - &r) * &twiddle_box;
+    let inv_size = FieldElement::<M31>::from(vals.len() as u64).inv().unwrap();
+    inv_size * &folded[0]
+}
 
-        vals = f0 + baryfac * &f1;
-        current_level += 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::CanonicCoset;
+    use crate::utils::{bit_reverse, random_values};
+
+    // fft.rs is the sequential reference, sizes go past the threshold of the parallel layers
+    #[test]
+    fn matches_the_recursive_fft() {
+        for log_size in [1, 2, 5, 11, 12, 13, 15] {
+            let domain = CanonicCoset::new(log_size).circle_domain();
+            let twiddles = TwiddleTree::new(domain);
+            let coeffs = random_values(1 << log_size, log_size as u64);
+
+            let mut expected = crate::fft::inv_fft(&coeffs, Some(&domain));
+            bit_reverse(&mut expected);
+            let mut vals = coeffs.clone();
+            inv_fft(&mut vals, &twiddles);
+            assert_eq!(vals, expected);
+
+            fft(&mut vals, &twiddles);
+            assert_eq!(vals, coeffs);
+        }
     }
-
-    vals[0] / T::from(size).unwrap()
 }
@@ -1,158 +1,254 @@
-use ndarray::{s, Array, ArrayView, ArrayBase, OwnedRepr, Dimension};
-
-use crate::{precomputes::{compute_bit_orders, get_subdomains, inverse_x, inverse_y}, utils::{log2, rbo_index_to_original, HALF}};
-
-const BASE_CASE_SIZE : u32= 64;
-const FOLDS_PER_ROUND : u32 = 3;
-const BASE : u32 = 2;
-const FOLD_SIZE_RATIO : u32= BASE.pow(FOLDS_PER_ROUND);
-const NUM_CHALLENGES : u32 = 80;
-
-pub fn fold<D>(mut values : &Array<u32, D>, coeff: u32, first_round: bool) -> &ArrayBase<OwnedRepr<u32>, D>
-where D : Dimension {
-
-    let (rbos, folded_rbos) = compute_bit_orders();
-    let sub_domains = get_subdomains();
-    let invx = inverse_x(sub_domains);
-    let invy = inverse_y(sub_domains);
-
-    for i in 0..FOLDS_PER_ROUND{
-        let full_len = values.shape()[1];
-        let half_len = full_len/2;
-        let new_shape =(half_len,);
-        let left = values.slice(s![..;2]);
-        let right = values.slice(s![1..;2]);
-        let mut f0 = Array::zeros(new_shape);
-        for j in 0..half_len{
-            let value = (left[j]+right[j])*HALF;
-            f0[j] = value;
+use lambdaworks_math::field::element::FieldElement;
+use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use lambdaworks_math::field::traits::{IsField, IsSubFieldOf};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::channel::ChannelField;
+use crate::circle::CircleImpl;
+use crate::domain::CanonicCoset;
+use crate::packed::{self, LANES};
+use crate::precomputes::TwiddleTree;
+use crate::utils::{batch_inverse, bit_reverse_index, HALF};
+
+// pairs folded by one thread at a time
+const FOLD_BLOCK: usize = 1 << 12;
+
+// values are in bit reversed order of the canonic domain (as in fast_fft), so every fold
+// combines neighbours 2k, 2k+1 and its output is again in bit reversed order
+// the first fold of a proof is over the circle domain (twiddle y), every later one over a
+// line (twiddle x)
+//
+// one fold of the pair (a, b) over the twiddle t: f0 + coeff*f1, f0 = (a+b)/2, f1 = (a-b)/(2t)
+fn fold_pair<F: IsField>(
+    a: &FieldElement<F>,
+    b: &FieldElement<F>,
+    inv_t: &FieldElement<M31>,
+    coeff: &FieldElement<F>,
+) -> FieldElement<F>
+where
+    M31: IsSubFieldOf<F>,
+{
+    let half = FieldElement::<M31>::from(HALF as u64);
+    let f0 = half * (a + b);
+    let f1 = half * inv_t * (a - b);
+    f0 + coeff * f1
+}
+
+// twiddle of the pair (2h, 2h+1) of a fold of 2^log_size values, the same value the
+// TwiddleTree::cached trees hold, computed on its own for the verifier's few positions
+fn fold_twiddle(log_size: u32, circle: bool, h: usize) -> FieldElement<M31> {
+    if circle {
+        let half_coset = CanonicCoset::new(log_size).half_coset();
+        half_coset.at(bit_reverse_index(h, log_size - 1)).get_y()
+    } else {
+        let line = CanonicCoset::new(log_size + 1).half_coset();
+        line.at(bit_reverse_index(h, log_size - 1)).get_x()
+    }
+}
+
+// one fold of the pairs (values[2k], values[2k+1]) over the twiddles 1/inv_twiddles[k]
+// (a + b)/2 and (a - b)/(2t) are computed coordinate by coordinate with packed::fold_halves,
+// only adding coeff times the second is done in F; fewer pairs than LANES fold one by one
+fn fold_pairs<F: ChannelField>(
+    values: &[FieldElement<F>],
+    inv_twiddles: &[FieldElement<M31>],
+    coeff: &FieldElement<F>,
+) -> Vec<FieldElement<F>>
+where
+    M31: IsSubFieldOf<F>,
+{
+    let n = inv_twiddles.len();
+    if n < LANES {
+        return values
+            .chunks_exact(2)
+            .zip(inv_twiddles)
+            .map(|(pair, inv_t)| fold_pair(&pair[0], &pair[1], inv_t, coeff))
+            .collect();
+    }
+    let halves = |i: usize| {
+        let coordinates: Vec<FieldElement<M31>> = values.iter().map(|v| F::coordinate(v, i)).collect();
+        let (mut f0, mut f1) = (vec![FieldElement::zero(); n], vec![FieldElement::zero(); n]);
+        packed::fold_halves(&coordinates, inv_twiddles, &mut f0, &mut f1);
+        (f0, f1)
+    };
+    let (f0, f1): (Vec<_>, Vec<_>) = (0..F::EXTENSION_DEGREE).map(halves).unzip();
+    let element = |f: &[Vec<FieldElement<M31>>], k: usize| {
+        let mut coordinates = [FieldElement::zero(); 4];
+        for (c, column) in coordinates.iter_mut().zip(f) {
+            *c = column[k];
         }
-        let folded_rbos_slice = folded_rbos[full_len..full_len*2].iter().step_by(2).collect();
-        let invy_slice = invy[full_len..full_len*2].to_vec();
-        let invx_slice = invx[full_len*2..full_len*3].to_vec();
-        let twiddle = if i == 0 && first_round {
-            ArrayView::from(folded_rbos_slice.iter().map(|&idx| invy[idx]).collect())
+        F::from_m31s(&coordinates[..F::EXTENSION_DEGREE])
+    };
+    (0..n).map(|k| element(&f0, k) + coeff * element(&f1, k)).collect()
+}
+
+// folds the values `folds` times, the first one being the circle fold if first_round
+// with the parallel feature every fold is split between threads, in blocks of FOLD_BLOCK pairs
+pub fn fold<F: ChannelField>(
+    values: &[FieldElement<F>],
+    coeff: &FieldElement<F>,
+    first_round: bool,
+    folds: u32,
+) -> Vec<FieldElement<F>>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    let mut values = values.to_vec();
+    for i in 0..folds {
+        let log_size = values.len().ilog2();
+        let tree;
+        let inv_twiddles = if i == 0 && first_round {
+            tree = TwiddleTree::cached(log_size);
+            tree.inv_y_twiddles()
         } else {
-            ArrayView::from(folded_rbos_slice.iter().map(|&idx| invx[idx]).collect())
+            tree = TwiddleTree::cached(log_size + 1);
+            tree.inv_x_twiddles(1)
         };
-        let left_ndim = left.ndim();
-        let twiddle_shape: Vec<usize> = std::iter::once(half_len) 
-        .chain(std::iter::repeat(1).take(left_ndim - 1)) 
-        .collect();
-        let twiddle_box = twiddle.to_owned().into_shape_clone(twiddle_shape).unwrap();
-        let mut f1;
-        for k in 0..half_len{
-            let value = (left[k] - right[k])*HALF*twiddle_box[k];
-        }
-        let mut new_values;
-        for l in 0..f0.len(){
-            new_values.push(f0[l] + f1[l]*coeff);
-        }
-        values = new_values;
+        let inv_twiddles = &inv_twiddles[..values.len() / 2];
+        #[cfg(feature = "parallel")]
+        let blocks = values.par_chunks(2 * FOLD_BLOCK).zip(inv_twiddles.par_chunks(FOLD_BLOCK));
+        #[cfg(not(feature = "parallel"))]
+        let blocks = values.chunks(2 * FOLD_BLOCK).zip(inv_twiddles.chunks(FOLD_BLOCK));
+        values = blocks
+            .map(|(block, inv_ts)| fold_pairs(block, inv_ts, coeff))
+            .collect::<Vec<_>>()
+            .concat();
     }
     values
 }
 
-pub fn fold_with_positions<D>(mut values : &Array<u32, D>, mut domain_size: usize,mut positions: &Array<usize,D>, coeff: u32, first_round: bool) -> &ArrayBase<OwnedRepr<u32>, D>
-where D : Dimension {
-    let sub_domains = get_subdomains();
-    let invx = inverse_x(sub_domains);
-    let invy = inverse_y(sub_domains);
-    positions = &positions.slice(s![..;2]).to_owned();
-    for i in 0..FOLDS_PER_ROUND{
-        let full_len = values.shape()[1];
-        let half_len = full_len/2;
-        let new_shape =(half_len,);
-        let left = values.slice(s![..;2]);
-        let right = values.slice(s![1..;2]);
-        let mut f0 = Array::zeros(new_shape);
-        for j in 0..half_len{
-            let value = (left[j]+right[j])*HALF;
-            f0[j] = value;
-        }
-        let twiddle;
-        if i == 0 && first_round{
-            let unrbo_positions = rbo_index_to_original(domain_size, positions, true);
-            let indices = &unrbo_positions+domain_size;
-            twiddle = ArrayView::from(indices.iter().map(|&idx| invy.get(idx)).collect());
-        } else {
-            let shifted_positions = (positions << 1) >> i;
-            let unrbo_positions = rbo_index_to_original(domain_size*2, &shifted_positions, true);
-            let indices = &unrbo_positions + (domain_size*2);
-            twiddle = ArrayView::from(indices.iter().map(|&idx| invx.get(idx)).collect());
-        }
-        let left_ndim = left.ndim();
-        let twiddle_shape: Vec<usize> = std::iter::once(half_len) 
-        .chain(std::iter::repeat(1).take(left_ndim - 1)) 
-        .collect();
-        let twiddle_box = twiddle.to_owned().into_shape_clone(twiddle_shape).unwrap();
-        let mut f1;
-        for k in 0..half_len{
-            let value = (left[k] - right[k])*HALF*twiddle_box[k];
-        }
-        let mut new_values;
-        for l in 0..f0.len(){
-            new_values.push(f0[l] + f1[l]*coeff);
-        }
-        values = new_values;
-        positions = &positions.slice(s![..;2]).to_owned();
-        domain_size = domain_size/2;
+// fold restricted to the queried positions of a domain of size 2^domain_log_size
+// values[2j], values[2j+1] are the siblings at positions[2j], positions[2j] ^ 1, and the
+// queried positions come in groups of 2^folds siblings, so every fold pairs up again
+// the twiddles of the positions are computed and inverted together, instead of building
+// the twiddle tree of the whole domain
+pub fn fold_with_positions<F: IsField>(
+    values: &[FieldElement<F>],
+    mut domain_log_size: u32,
+    positions: &[usize],
+    coeff: &FieldElement<F>,
+    first_round: bool,
+    folds: u32,
+) -> Vec<FieldElement<F>>
+where
+    M31: IsSubFieldOf<F>,
+{
+    assert_eq!(values.len(), positions.len());
+    assert!(positions.len().is_multiple_of(1 << folds));
+    let mut values = values.to_vec();
+    let mut positions = positions.to_vec();
+    for i in 0..folds {
+        positions = positions.iter().step_by(2).map(|p| p / 2).collect();
+        let twiddles: Vec<FieldElement<M31>> = positions
+            .iter()
+            .map(|p| fold_twiddle(domain_log_size, i == 0 && first_round, *p))
+            .collect();
+        let inv_twiddles = batch_inverse(&twiddles).expect("twiddles are never zero");
+        values = values
+            .chunks_exact(2)
+            .zip(&inv_twiddles)
+            .map(|(pair, inv_t)| fold_pair(&pair[0], &pair[1], inv_t, coeff))
+            .collect();
+        domain_log_size -= 1;
     }
     values
 }
 
-pub fn prove_low_degree<D>(evaluations: &Array<usize, D>, extra_entropy: &Vec<u8>) where D:Dimension{
-    //commit merkle root 
-    let (rbos, folded_rbos) = compute_bit_orders();
-    let indices = &folded_rbos[evaluations.len()..evaluations.len()*2];
-    let values = ArrayView::from(indices.iter().map(|&x| evaluations.get(x)).collect());
-    let leaves;
-    let roots;
-    let trees;
-    //prove descent
-    let rounds = (log2(evaluations.len() as u32) / BASE_CASE_SIZE as usize) / FOLDS_PER_ROUND as usize;
-    for i in 0..rounds{
-        
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qm31::{QM31Impl, QM31};
+    use crate::utils::random_values;
+
+    fn random_qm31s(n: usize, seed: u64) -> Vec<FieldElement<QM31>> {
+        random_values(4 * n, seed)
+            .chunks_exact(4)
+            .map(|c| FieldElement::<QM31>::from_m31(c[0], c[1], c[2], c[3]))
+            .collect()
     }
 
-}
+    const FOLDS: u32 = 3;
+
+    // folds with the x and y of the domain points themselves
+    fn fold_by_points(values: &[FieldElement<M31>], coeff: &FieldElement<M31>) -> Vec<FieldElement<M31>> {
+        let log_size = values.len().ilog2();
+        let domain = CanonicCoset::new(log_size).circle_domain();
+        let points: Vec<_> = (0..values.len())
+            .step_by(2)
+            .map(|i| domain.at(bit_reverse_index(i, log_size)))
+            .collect();
+        let two = FieldElement::<M31>::from(2);
+        let mut values: Vec<_> = values
+            .chunks_exact(2)
+            .zip(&points)
+            .map(|(pair, p)| (pair[0] + pair[1]) / two + coeff * (pair[0] - pair[1]) / (two * p.get_y()))
+            .collect();
+        let mut xs: Vec<_> = points.iter().map(|p| p.get_x()).collect();
+        for _ in 1..FOLDS {
+            values = values
+                .chunks_exact(2)
+                .zip(xs.iter().step_by(2))
+                .map(|(pair, x)| (pair[0] + pair[1]) / two + coeff * (pair[0] - pair[1]) / (two * x))
+                .collect();
+            xs = xs.iter().step_by(2).map(|x| two * x * x - FieldElement::one()).collect();
+        }
+        values
+    }
+
+    #[test]
+    fn folds_match_the_domain_points() {
+        let log_size = 10;
+        let values: Vec<FieldElement<M31>> =
+            (0..1u64 << log_size).map(|i| FieldElement::from(i * i + 7)).collect();
+        let coeff = FieldElement::<M31>::from(12345);
+        let folded = fold(&values, &coeff, true, FOLDS);
+        assert_eq!(folded, fold_by_points(&values, &coeff));
 
-//     for i in range(rounds):
-//         leaves.append(values)
-//         trees.append(merkelize_top_dimension(values.reshape(
-//             (len(values) // FOLD_SIZE_RATIO, FOLD_SIZE_RATIO)
-//             + values.shape[1:]
-//         )))
-//         roots.append(trees[-1][1])
-//         
-//         fold_factor = ExtendedM31(get_challenges(b''.join(roots), modulus, 4))
-//         values = fold(values, fold_factor, i==0)
-//     entropy = extra_entropy + b''.join(roots) + values.tobytes()
-//     challenges = get_challenges(
-//         entropy, len(evaluations) >> FOLDS_PER_ROUND, NUM_CHALLENGES
-//     )
-//     round_challenges = (
-//         challenges.reshape((1,)+challenges.shape)
-//         >> cp.arange(0, rounds * FOLDS_PER_ROUND, FOLDS_PER_ROUND)
-//         .reshape((rounds,) + (1,) * challenges.ndim)
-//     )
-
-//     branches = [
-//         [get_branch(tree, c) for c in r_challenges]
-//         for i, (r_challenges, tree) in enumerate(zip(round_challenges, trees))
-//     ]
-//     round_challenges_xfold = (
-//         round_challenges.reshape(round_challenges.shape + (1,)) * 8
-//         + cp.arange(FOLD_SIZE_RATIO).reshape(1, 1, FOLD_SIZE_RATIO)
-//     )
-
-//     leaf_values = [
-//         leaves[i][round_challenges_xfold[i]]
-//         for i in range(rounds)
-//     ]
-//     return {
-//         "roots": roots,
-//         "branches": branches,
-//         "leaf_values": leaf_values,
-//         "final_values": values
-//     }
\ No newline at end of file
+        // a queried group of siblings folds to the same value as the whole domain
+        let group = 37;
+        let ratio = 1 << FOLDS;
+        let positions: Vec<usize> = (group * ratio..(group + 1) * ratio).collect();
+        let queried: Vec<_> = positions.iter().map(|p| values[*p]).collect();
+        let folded_group = fold_with_positions(&queried, log_size, &positions, &coeff, true, FOLDS);
+        assert_eq!(folded_group, vec![folded[group]]);
+    }
+
+    fn scalar_fold_pairs<F: IsField>(
+        values: &[FieldElement<F>],
+        inv_ts: &[FieldElement<M31>],
+        coeff: &FieldElement<F>,
+    ) -> Vec<FieldElement<F>>
+    where
+        M31: IsSubFieldOf<F>,
+    {
+        values
+            .chunks_exact(2)
+            .zip(inv_ts)
+            .map(|(pair, inv_t)| fold_pair(&pair[0], &pair[1], inv_t, coeff))
+            .collect()
+    }
+
+    #[test]
+    fn packed_fold_matches_the_scalar_fold() {
+        // pair counts below LANES, and ones that aren't a multiple of it
+        for n in [5, 3 * LANES + 5, FOLD_BLOCK + 7] {
+            let inv_ts = random_values(n, 1);
+            let (values, coeff) = (random_qm31s(2 * n, 2), random_qm31s(1, 3)[0]);
+            assert_eq!(fold_pairs(&values, &inv_ts, &coeff), scalar_fold_pairs(&values, &inv_ts, &coeff));
+            let (values, coeff) = (random_values(2 * n, 4), random_values(1, 5)[0]);
+            assert_eq!(fold_pairs(&values, &inv_ts, &coeff), scalar_fold_pairs(&values, &inv_ts, &coeff));
+        }
+
+        // several blocks of extension values, against the scalar fold of every position
+        let log_size = 14;
+        let values = random_qm31s(1 << log_size, 6);
+        let coeff = random_qm31s(1, 7)[0];
+        let positions: Vec<usize> = (0..1 << log_size).collect();
+        assert_eq!(
+            fold(&values, &coeff, true, FOLDS),
+            fold_with_positions(&values, log_size, &positions, &coeff, true, FOLDS)
+        );
+    }
+}
@@ -1,60 +1,20 @@
-use std::ops::Add;
-
-use crate::circle::{
-    div, multiply, scalar_division, scalar_multiply, subtract, CircleImpl, CirclePoint, MODULUS,
-};
+use crate::circle::{CircleImpl, CirclePoint};
+use crate::domain::{CanonicCoset, CircleDomain, Coset};
+use crate::utils::batch_inverse;
 use lambdaworks_math::field::element::FieldElement;
 use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use lambdaworks_math::field::traits::{IsField, IsSubFieldOf};
 
-// all the functions are specific to M31
-pub fn get_generator(field_modulus: u32) -> CirclePoint {
-    for y in 2..field_modulus {
-        let y_pt: FieldElement<M31> = FieldElement::new(y);
-        let x_pt: FieldElement<M31> = FieldElement::new(1 - y * y).sqrt().unwrap().0; // check docs for once , if its the first value or not
-        let mut point = <CirclePoint as CircleImpl>::new_with_field_elements(x_pt, y_pt);
-        let point_cloned = point.clone();
-        for _ in 0..(f64::log2((field_modulus + 1) as f64) as usize - 1) {
-            point = point.double();
-        }
-
-        if point != <CirclePoint as CircleImpl>::new(1, 0) {
-            return point_cloned;
-        }
-    }
-    panic!("Could not find generator");
-}
-
-pub fn get_initial_domain_of_size(field_modulus: u32, size: usize) -> Vec<CirclePoint> {
-    assert!(size < field_modulus as usize);
-    let mut g = get_generator(field_modulus);
-
-    for _ in 0..(f64::log2((field_modulus + 1) as f64 / size as f64) as usize - 1) {
-        g = g.double();
-    }
-
-    let gx2 = g.double();
-    let mut domain = vec![g];
-    for _ in 1..size {
-        let temp_domain_last = domain.last().unwrap();
-        let res = gx2.add(*temp_domain_last);
-        domain.push(res);
-    }
-    domain
-}
-
-pub fn get_single_domain_value(field_modulus: u32, size: usize, index: usize) -> CirclePoint {
-    assert!(size < field_modulus as usize);
-    let mut g = get_generator(field_modulus);
-
-    for _ in 0..(f64::log2((field_modulus + 1) as f64 / size as f64) as usize - 1) {
-        g = g.double(); // cause we need to double the field elements , not according to CirclePoint numbers like in (FieldElement<M31>,FieldElement<M31>)
-    }
-
-    scalar_multiply(g, (2 * index + 1) as u32)
-}
+// the domain points are always over M31, the values can be M31 or an extension of it (QM31)
+// fft: evaluations -> coefficients, inv_fft: coefficients -> evaluations
+//
+// evaluations are in the natural order of the CircleDomain (half coset, then its conjugates)
+// coefficients are in the circle polynomial basis, where bit j of the index of a coefficient
+// selects the j-th factor of 1, y, x, 2x^2-1, 2(2x^2-1)^2-1, ...
+// i.e. the basis reads 1, y, x, xy, 2x^2-1, y(2x^2-1), x(2x^2-1), ...
 
 // for circle points onlys
-pub fn halve_domain(domain: &Vec<CirclePoint>, preserve_length: bool) -> Vec<CirclePoint> {
+pub fn halve_domain(domain: &[CirclePoint], preserve_length: bool) -> Vec<CirclePoint> {
     let new_length = if preserve_length {
         domain.len()
     } else {
@@ -64,100 +24,210 @@ pub fn halve_domain(domain: &Vec<CirclePoint>, preserve_length: bool) -> Vec<Cir
 }
 
 pub fn halve_single_domain_value(value: &CirclePoint) -> FieldElement<M31> {
-    value.get_x().clone()
+    value.get_x()
+}
+
+// defaults to the domain of the canonic coset of the same size as vals
+fn default_domain(size: usize) -> CircleDomain {
+    CanonicCoset::new(size.ilog2()).circle_domain()
+}
+
+// put a at the even positions and b at the odd ones
+fn interleave<F: IsField>(a: Vec<FieldElement<F>>, b: Vec<FieldElement<F>>) -> Vec<FieldElement<F>> {
+    a.into_iter().zip(b).flat_map(|(l, r)| [l, r]).collect()
 }
 
-pub fn fft(vals:&Vec<CirclePoint>, domain: Option<&Vec<CirclePoint>>) -> Vec<CirclePoint> {
+pub fn fft<F: IsField>(
+    vals: &[FieldElement<F>],
+    domain: Option<&CircleDomain>,
+) -> Vec<FieldElement<F>>
+where
+    M31: IsSubFieldOf<F>,
+{
+    let domain = match domain {
+        Some(d) => *d,
+        None => default_domain(vals.len()),
+    };
+    assert!(domain.size() == vals.len());
+
+    // f(x,y) = f0(x) + y*f1(x), using that at(i) and at(i + half) are (x,y), (x,-y)
+    let half = vals.len() / 2;
+    let half_coset = domain.half_coset();
+    let ys: Vec<FieldElement<M31>> = half_coset.iter().map(|p| p.get_y()).collect();
+    let inv_y = batch_inverse(&ys).expect("twiddles are never zero");
+    let (left, right) = vals.split_at(half);
+    let f0: Vec<FieldElement<F>> = left.iter().zip(right).map(|(l, r)| l + r).collect();
+    let f1: Vec<FieldElement<F>> = left
+        .iter()
+        .zip(right)
+        .zip(inv_y)
+        .map(|((l, r), t)| t * (l - r))
+        .collect();
+
+    // every layer skipped the division by 2, do all of them at once
+    let inv_size = FieldElement::<M31>::from(vals.len() as u64).inv().unwrap();
+    interleave(line_fft(f0, half_coset), line_fft(f1, half_coset))
+        .into_iter()
+        .map(|c| inv_size * c)
+        .collect()
+}
+
+// vals[i] = g(x(coset.at(i))), where at(i) and at(i + size/2) are antipodal so their x are -x, x
+// g(x) = g0(2x^2-1) + x*g1(2x^2-1), and g0, g1 live on the doubled coset
+fn line_fft<F: IsField>(vals: Vec<FieldElement<F>>, coset: Coset) -> Vec<FieldElement<F>>
+where
+    M31: IsSubFieldOf<F>,
+{
     if vals.len() == 1 {
-        return vals.to_vec();
+        return vals;
     }
+    let half = vals.len() / 2;
+    let xs: Vec<FieldElement<M31>> = coset.iter().take(half).map(|p| p.get_x()).collect();
+    let inv_x = batch_inverse(&xs).expect("twiddles are never zero");
+    let (left, right) = vals.split_at(half);
+    let g0: Vec<FieldElement<F>> = left.iter().zip(right).map(|(l, r)| l + r).collect();
+    let g1: Vec<FieldElement<F>> = left
+        .iter()
+        .zip(right)
+        .zip(inv_x)
+        .map(|((l, r), t)| t * (l - r))
+        .collect();
+    let doubled = coset.double();
+    interleave(line_fft(g0, doubled), line_fft(g1, doubled))
+}
 
+pub fn inv_fft<F: IsField>(
+    coeffs: &[FieldElement<F>],
+    domain: Option<&CircleDomain>,
+) -> Vec<FieldElement<F>>
+where
+    M31: IsSubFieldOf<F>,
+{
     let domain = match domain {
-        Some(d) => d.to_vec(),
-        None => get_initial_domain_of_size(MODULUS, vals.len()),
-    };
-    let half_domain = halve_domain(&domain, false); //get the half length
-
-    let (f0, f1) = if let Some(_cir) = domain.get(0) {
-        let (left, right) = vals.split_at(vals.len() / 2);
-        let right_reversed: Vec<CirclePoint> = right.iter().rev().cloned().collect();
-        let f0: Vec<_> = left
-            .iter()
-            .zip(&right_reversed)
-            .map(|(&l, &r)| (scalar_division(l.add(r), 2)))
-            .collect();
-        let f1: Vec<_> = left
-            .iter()
-            .zip(&right_reversed)
-            .zip(domain.iter())
-            .map(|((&l, &r), d)| div(subtract(l, r), scalar_multiply(*d, 2)))
-            .collect();
-        (f0, f1)
-    } else {
-        let (left, right) = vals.split_at(vals.len() / 2);
-        let right_reversed: Vec<_> = right.iter().rev().cloned().collect();
-        let f0: Vec<_> = left
-            .iter()
-            .zip(&right_reversed)
-            .map(|(&l, &r)| scalar_division(l.add(r), 2))
-            .collect();
-        let f1: Vec<_> = left
-            .iter()
-            .zip(&right_reversed)
-            .zip(domain.iter())
-            .map(|((&l, &r), &x)| div(subtract(l, r), scalar_multiply(x, 2)))
-            .collect();
-        (f0, f1)
+        Some(d) => *d,
+        None => default_domain(coeffs.len()),
     };
+    assert!(domain.size() == coeffs.len());
+
+    let half_coset = domain.half_coset();
+    let f0 = line_inv_fft(coeffs.iter().step_by(2).cloned().collect(), half_coset);
+    let f1 = line_inv_fft(coeffs.iter().skip(1).step_by(2).cloned().collect(), half_coset);
+
+    let (left, right): (Vec<FieldElement<F>>, Vec<FieldElement<F>>) = f0
+        .iter()
+        .zip(&f1)
+        .zip(half_coset.iter())
+        .map(|((a, b), p)| {
+            let yb = p.get_y() * b;
+            (a + &yb, a - yb)
+        })
+        .unzip();
 
-    let mut result = vec![CirclePoint::zero(); domain.len()];
-    let half_result = fft(&f0, Some(&half_domain));
-    for (i, &val) in half_result.iter().enumerate() {
-        result[i * 2] = val;
-    }
-    let half_result = fft(&f1, Some(&half_domain));
-    for (i, &val) in half_result.iter().enumerate() {
-        result[i * 2 + 1] = val;
+    let mut result = left;
+    result.extend(right);
+    result
+}
+
+fn line_inv_fft<F: IsField>(coeffs: Vec<FieldElement<F>>, coset: Coset) -> Vec<FieldElement<F>>
+where
+    M31: IsSubFieldOf<F>,
+{
+    if coeffs.len() == 1 {
+        return coeffs;
     }
+    let doubled = coset.double();
+    let g0 = line_inv_fft(coeffs.iter().step_by(2).cloned().collect(), doubled);
+    let g1 = line_inv_fft(coeffs.iter().skip(1).step_by(2).cloned().collect(), doubled);
+
+    let (left, right): (Vec<FieldElement<F>>, Vec<FieldElement<F>>) = g0
+        .iter()
+        .zip(&g1)
+        .zip(coset.iter())
+        .map(|((a, b), p)| {
+            let xb = p.get_x() * b;
+            (a + &xb, a - xb)
+        })
+        .unzip();
 
+    let mut result = left;
+    result.extend(right);
     result
 }
 
-pub fn inv_fft(vals: &Vec<CirclePoint>, domain: Option<&Vec<CirclePoint>>) -> Vec<CirclePoint> {
-    if vals.len() == 1 {
-        return vals.to_vec();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::CirclePointIndex;
+    use crate::fast_fft;
+    use crate::precomputes::TwiddleTree;
+    use crate::qm31::{QM31Impl, QM31};
+    use crate::utils::random_values;
+
+    // k-th element of the basis 1, y, x, xy, 2x^2-1, ... at p
+    fn basis_at(k: usize, p: &CirclePoint) -> FieldElement<M31> {
+        let mut res = FieldElement::one();
+        if k & 1 == 1 {
+            res *= p.get_y();
+        }
+        let mut x = p.get_x();
+        let mut bits = k >> 1;
+        while bits > 0 {
+            if bits & 1 == 1 {
+                res *= x;
+            }
+            x = x.square().double() - FieldElement::one();
+            bits >>= 1;
+        }
+        res
     }
 
-    let domain = match domain {
-        Some(d) => d.to_vec(),
-        None => get_initial_domain_of_size(MODULUS, vals.len()),
-    };
-    let half_domain = halve_domain(&domain, false);
-
-    let f0 = inv_fft(
-        &vals.iter().step_by(2).cloned().collect::<Vec<_>>(),
-        Some(&half_domain),
-    );
-    let f1 = inv_fft(
-        &vals.iter().skip(1).step_by(2).cloned().collect::<Vec<_>>(),
-        Some(&half_domain),
-    );
-
-    let (left, right): (Vec<CirclePoint>, Vec<CirclePoint>) = if let Some(_cir) = domain.get(0) {
-        f0.iter()
-            .zip(&f1)
-            .zip(domain.iter())
-            .map(|((&l, &r), &y)| (l.add(multiply(y, r)), subtract(l, multiply(y, r))))
-            .unzip()
-    } else {
-        f0.iter()
-            .zip(&f1)
-            .zip(domain.iter())
-            .map(|((&l, &r), &x)| (l.add(multiply(x, r)), subtract(l, multiply(x, r))))
-            .unzip()
-    };
+    // every size from 2 to 2^20; past 2^12 the recursive fft is too slow without optimizations,
+    // so those sizes go through fast_fft and its cached twiddles
+    #[test]
+    fn round_trip_on_every_size() {
+        for log_size in 1..=12 {
+            let domain = CanonicCoset::new(log_size).circle_domain();
+            let coeffs = random_values(1 << log_size, log_size as u64);
+            let evals = inv_fft(&coeffs, Some(&domain));
+            assert_eq!(fft(&evals, Some(&domain)), coeffs);
+        }
+        for log_size in 13..=20 {
+            let twiddles = TwiddleTree::cached(log_size);
+            let coeffs = random_values(1 << log_size, log_size as u64);
+            let mut vals = coeffs.clone();
+            fast_fft::inv_fft(&mut vals, &twiddles);
+            fast_fft::fft(&mut vals, &twiddles);
+            assert_eq!(vals, coeffs);
+        }
+    }
 
-    let mut result = left;
-    result.extend(right.iter().rev().cloned());
-    result
+    #[test]
+    fn coefficients_are_in_the_circle_basis() {
+        for log_size in 1..=6 {
+            let domain = CanonicCoset::new(log_size).circle_domain();
+            let coeffs = random_values(1 << log_size, 100 + log_size as u64);
+            let evals: Vec<FieldElement<M31>> = domain
+                .iter()
+                .map(|p| {
+                    coeffs
+                        .iter()
+                        .enumerate()
+                        .fold(FieldElement::zero(), |acc, (k, c)| acc + c * basis_at(k, &p))
+                })
+                .collect();
+            assert_eq!(inv_fft(&coeffs, Some(&domain)), evals);
+            assert_eq!(fft(&evals, None), coeffs);
+        }
+    }
+
+    #[test]
+    fn works_on_shifted_domains_and_extension_values() {
+        let domain = CircleDomain::new(Coset::new(CirclePointIndex(12345), 4));
+        let coeffs: Vec<FieldElement<QM31>> = random_values(4 * 32, 7)
+            .chunks(4)
+            .map(|c| FieldElement::<QM31>::from_m31(c[0], c[1], c[2], c[3]))
+            .collect();
+        let evals = inv_fft(&coeffs, Some(&domain));
+        assert_eq!(fft(&evals, Some(&domain)), coeffs);
+    }
 }
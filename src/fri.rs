@@ -1,289 +1,929 @@
-use crate::merkle::{merkelize, hash, verify_branch, get_branch};
-use crate::fft::{fft, inv_fft, get_initial_domain_of_size, halve_domain, get_single_domain_value, halve_single_domain_value};
-use crate::circle::{div, scalar_division, scalar_multiply, subtract, CircleImpl, CirclePoint, MODULUS};
-use std::ops::Add;
+use crate::channel::{Blake2sHash, Blake3Hash, Channel, ChannelField, ChannelHash, HashChannel, Sha256Hash};
+use crate::circle::MODULUS;
+use crate::fast_fft::line_fft;
+use crate::fast_fri::{fold, fold_with_positions};
+use crate::merkle::{HashMerkleHasher, MerkleTree, MultiProof};
+use crate::poly::CircleEvaluation;
+use crate::precomputes::TwiddleTree;
+use crate::qm31::{QM31, QM31Impl};
+use crate::utils::bit_reverse;
 use lambdaworks_math::field::element::FieldElement;
 use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use lambdaworks_math::field::traits::IsSubFieldOf;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-const BASE_CASE_SIZE : u32= 128;
-const FOLDS_PER_ROUND : u32 = 3;
-const BASE : u32 = 2;
-const FOLD_SIZE_RATIO : u32= BASE.pow(FOLDS_PER_ROUND);
-const NUM_CHALLENGES : u32 = 80;
-
-pub fn extend_trace(field: u32, trace: &Vec<CirclePoint>) -> Vec<CirclePoint>{
-    let small_domain = get_initial_domain_of_size(field, trace.len());
-    let coeffs = fft(trace, Some(&small_domain));
-    let big_domain = get_initial_domain_of_size(field, trace.len()*2);
-    let res = inv_fft(trace, Some(&big_domain));
-    res
+// challenges are drawn from QM31, whose size caps the security of any config
+const SECURE_FIELD_BITS: u32 = 124;
+const MAX_LOG_BLOWUP: u32 = 16;
+const MAX_FOLD_STEP: u32 = 4;
+const MAX_LOG_LAST_LAYER_DEGREE_BOUND: u32 = 20;
+// u64 pow values, see Channel::pow_value
+const MAX_POW_BITS: u32 = 64;
+
+// parameters of a FRI proof, fixed by the verifier and recorded in the proof
+// the evaluations have rate 2^-log_blowup, every round folds fold_step times (commits to
+// leaves of 2^fold_step values) until the degree bound is 2^log_last_layer_degree_bound,
+// whose 2^(log_last_layer_degree_bound + log_blowup) values are sent in full
+// hash is used for both the Merkle trees and the channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FriConfig {
+    pub log_blowup: u32,
+    pub log_last_layer_degree_bound: u32,
+    pub fold_step: u32,
+    pub n_queries: usize,
+    pub pow_bits: u32,
+    pub hash: FriHash,
 }
 
-pub fn line_function(P1: CirclePoint, P2: CirclePoint, domain: &[CirclePoint]) ->Vec<FieldElement<M31>>{
-    let x1 = P1.get_x();
-    let x2 = P2.get_x();
-    let y1 = P1.get_y();
-    let y2 = P2.get_y();
-    let denominator = x1*y2 - x2*y1;
-    let a = (y2-y1) / denominator;
-    let b = (x1-x2) / denominator;
-    let negative : FieldElement<M31> = FieldElement::new(MODULUS-1);
-    let c = negative * (a*x1 + b*y1);
-    domain.iter().map(|cpoint| a*(cpoint.get_x()) + b*cpoint.get_y() + c).collect()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FriHash {
+    Sha256,
+    Blake2s,
+    Blake3,
 }
 
-pub fn rbo(values: &Vec<CirclePoint>) -> Vec<CirclePoint> {
-    if values.len() == 1{
-        return values.to_vec();
-    }
-    let x : Vec<CirclePoint> = values.iter().cloned().step_by(2).collect();
-    let y : Vec<CirclePoint> = values.iter().cloned().skip(1).step_by(2).collect();
-    let L = rbo(&x);
-    let R = rbo(&y);
-    let mut res = L;
-    res.extend(R);
-    return res;
+impl FriHash {
+    fn to_byte(self) -> u8 {
+        match self {
+            FriHash::Sha256 => 0,
+            FriHash::Blake2s => 1,
+            FriHash::Blake3 => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, FriConfigError> {
+        match byte {
+            0 => Ok(FriHash::Sha256),
+            1 => Ok(FriHash::Blake2s),
+            2 => Ok(FriHash::Blake3),
+            _ => Err(FriConfigError::Hash(byte)),
+        }
+    }
 }
 
-pub fn folded_reverse_bit_order(values: &Vec<CirclePoint>) -> Vec<CirclePoint>{
-    let l : Vec<CirclePoint>= values.iter().cloned().step_by(2).collect();
-    let r : Vec<CirclePoint>= values.iter().cloned().skip(1).step_by(2).rev().collect();
-    let L = rbo(&l);
-    let R = rbo(&r);
-    let mut o = vec![None; values.len()];
-    let mut i = 0;
-    for x in L{
-        o[i] = Some(x);
-        i+=2;
-    }
-    let mut j = 1;
-    for x in R{
-        o[j] = Some(x);
-        j+=2;
-    }
-    let res: Vec<CirclePoint> = o.into_iter().filter_map(|u| u).collect();
-    res
+#[derive(Debug, PartialEq, Eq)]
+pub enum FriConfigError {
+    LogBlowup(u32),
+    LogLastLayerDegreeBound(u32),
+    FoldStep(u32),
+    NQueries(usize),
+    PowBits(u32),
+    Hash(u8),
+    // the header is not FriConfig::HEADER_SIZE bytes long
+    HeaderLength(usize),
 }
 
-pub fn rbo_index_to_original(length: usize, index: usize) -> usize {
-    let sub = format!("{:b}", length + index)[3..(format!("{:b}", length + index).len() - 1)]
-        .chars()
-        .rev()
-        .collect::<String>();
-    let sub2 = usize::from_str_radix(sub.as_str(), 2);
+impl FriConfig {
+    pub const HEADER_SIZE: usize = 9;
 
-    if index % 2 == 0{
-        return sub2.unwrap();
-    } else {
-        return length - 1 - (sub2.unwrap())*2;
+    pub fn new(
+        log_blowup: u32,
+        log_last_layer_degree_bound: u32,
+        fold_step: u32,
+        n_queries: usize,
+        pow_bits: u32,
+    ) -> Result<Self, FriConfigError> {
+        let hash = FriHash::Sha256;
+        let config = FriConfig { log_blowup, log_last_layer_degree_bound, fold_step, n_queries, pow_bits, hash };
+        config.validate()?;
+        Ok(config)
+    }
+
+    // the config with another hash, SHA-256 being the default
+    pub fn with_hash(self, hash: FriHash) -> Self {
+        FriConfig { hash, ..self }
     }
-}
 
-pub fn fold(mut values: Vec<CirclePoint>, coeff: u32, mut domain: Vec<CirclePoint>) -> (Vec<CirclePoint>, Vec<CirclePoint>) {
-    for i in 0..FOLDS_PER_ROUND{
-        let left : Vec<CirclePoint>= values.iter().cloned().step_by(2).collect();
-        let right : Vec<CirclePoint>= values.iter().cloned().skip(1).step_by(2).collect();
-        let mut f0: Vec<Option<CirclePoint>> = vec![None; values.len()/2];
-        for j in 0..(values.len()/2) {
-            let L = left[j];
-            let R = right[j];
-            // instead of L+R, use circle point addition
-            f0[j] = Some(scalar_division(L+R, 2));
+    pub fn validate(&self) -> Result<(), FriConfigError> {
+        if !(1..=MAX_LOG_BLOWUP).contains(&self.log_blowup) {
+            return Err(FriConfigError::LogBlowup(self.log_blowup));
+        }
+        if self.log_last_layer_degree_bound > MAX_LOG_LAST_LAYER_DEGREE_BOUND {
+            return Err(FriConfigError::LogLastLayerDegreeBound(self.log_last_layer_degree_bound));
+        }
+        if !(1..=MAX_FOLD_STEP).contains(&self.fold_step) {
+            return Err(FriConfigError::FoldStep(self.fold_step));
         }
-        
-        let domain_tmp :Vec<CirclePoint> = domain.iter().cloned().step_by(2).collect();
-        let mut f1 : Vec<Option<CirclePoint>> = vec![None; f0.len()];
-        for k in 0..(values.len()/2){
-            let L = left[k];
-            let R = right[k];
-            let x = domain_tmp[k];
-                // not sure about the type of x, might have to use scalar multiply 
-            f1[k] = Some(div(subtract(L, R),scalar_multiply(x, 2)));
+        if self.n_queries == 0 || self.n_queries > u32::MAX as usize {
+            return Err(FriConfigError::NQueries(self.n_queries));
         }
-        let mut vals = vec![None;f0.len()];
-        for i in 0..f0.len(){
-            let f0val = f0[i].unwrap();
-            let f1val = f1[i].unwrap();
-            vals[i] = Some(f0val.add(scalar_multiply(f1val, coeff)));
+        if self.pow_bits > MAX_POW_BITS {
+            return Err(FriConfigError::PowBits(self.pow_bits));
         }
-        let vals2 : Vec<CirclePoint> = vals.into_iter().filter_map(|e| e).collect();
-        values = vals2;
-        let domain2= halve_domain(&domain_tmp, true);
-        domain = domain2;
+        Ok(())
+    }
+
+    // log size of the last layer, sent in full
+    pub fn log_last_layer_size(&self) -> u32 {
+        self.log_last_layer_degree_bound + self.log_blowup
+    }
+
+    // every query of a code of rate rho passes with probability rho for a far word under the
+    // usual conjecture, each bit of grinding adds one bit
+    pub fn conjectured_security_bits(&self) -> u32 {
+        let bits = self.n_queries as u64 * self.log_blowup as u64 + self.pow_bits as u64;
+        bits.min(SECURE_FIELD_BITS as u64) as u32
     }
-    return (values, domain);
-}
 
-pub fn get_challenges(root: &[u8], domain_size: u32, num_challenges: usize) -> Vec<u32>{
-    let mut challenge_data = Vec::new();
-    for i in 0..((num_challenges + 7) / 8) {
-        let mut hash_input = Vec::new();
-        hash_input.extend_from_slice(root);
-        hash_input.push((i / 256) as u8);
-        hash_input.push((i % 256) as u8);
-        let hash_output = hash(hash_input);
-        challenge_data.extend_from_slice(&hash_output);
-    }
-
-    (0..num_challenges)
-        .map(|i| {
-            let start = i * 4;
-            let end = start + 4;
-            let value = u32::from_le_bytes(
-                challenge_data[start..end]
-                    .try_into()
-                    .expect("Invalid slice length"),
-            );
-            value % domain_size
-        }).collect()
+    // provable up to the Johnson bound, where a query only catches a far word with
+    // probability 1 - sqrt(rho), i.e. about half the bits per query. On top of that the
+    // folding challenges can land on a bad linear combination (the proximity gap error), with
+    // probability about domain_size^2 / |F|, which grinding doesn't help with
+    pub fn proven_security_bits(&self, log_domain_size: u32) -> u32 {
+        let query_bits = self.n_queries as u64 * self.log_blowup as u64 / 2 + self.pow_bits as u64;
+        let commit_bits = SECURE_FIELD_BITS.saturating_sub(2 * log_domain_size) as u64;
+        query_bits.min(commit_bits) as u32
+    }
+
+    // header of a proof: one byte for each of log_blowup, log_last_layer_degree_bound,
+    // fold_step, pow_bits and hash, then n_queries as a little endian u32
+    pub fn to_bytes(&self) -> [u8; Self::HEADER_SIZE] {
+        let mut bytes = [0; Self::HEADER_SIZE];
+        bytes[0] = self.log_blowup as u8;
+        bytes[1] = self.log_last_layer_degree_bound as u8;
+        bytes[2] = self.fold_step as u8;
+        bytes[3] = self.pow_bits as u8;
+        bytes[4] = self.hash.to_byte();
+        bytes[5..].copy_from_slice(&(self.n_queries as u32).to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FriConfigError> {
+        if bytes.len() != Self::HEADER_SIZE {
+            return Err(FriConfigError::HeaderLength(bytes.len()));
+        }
+        let hash = FriHash::from_byte(bytes[4])?;
+        let config = FriConfig::new(
+            bytes[0] as u32,
+            bytes[1] as u32,
+            bytes[2] as u32,
+            u32::from_le_bytes(bytes[5..].try_into().unwrap()) as usize,
+            bytes[3] as u32,
+        )?;
+        Ok(config.with_hash(hash))
+    }
 }
 
-pub fn is_rbo_low_degree(evaluations: &Vec<CirclePoint>, domain: &Vec<CirclePoint>) -> bool{
-    let halflen = evaluations.len()/2;
-    let o = fft(&folded_reverse_bit_order(evaluations), Some(&folded_reverse_bit_order(domain)));
-    let zero = CirclePoint::zero();
-    return o[halflen..].iter().all(|&c| c == zero );
+impl Default for FriConfig {
+    // ~100 conjectured bits at blowup 2 (the values the module used to hard code)
+    fn default() -> Self {
+        FriConfig {
+            log_blowup: 1,
+            log_last_layer_degree_bound: 6,
+            fold_step: 3,
+            n_queries: 80,
+            pow_bits: 20,
+            hash: FriHash::Sha256,
+        }
+    }
 }
 
-//need to_bytes and from_bytes functions for CirclePoint
+// the digests of every FriHash
+type Digest = [u8; 32];
+const DIGEST_SIZE: usize = 32;
 
-pub fn chunkify(values: &Vec<CirclePoint>) -> Vec<Vec<u8>>{
-    let mut v = Vec::new();
-    for i in 0..values.len(){
-        let element = values[i..i + (FOLD_SIZE_RATIO as usize)]
+// the leaves of a layer are its groups of 2^folds consecutive values, which fold into one
+// value of the next layer. A leaf is hashed as the little endian bytes of the coordinates
+fn leaf_bytes(values: &[FieldElement<QM31>]) -> Vec<u8> {
+    values
         .iter()
-        .flat_map(|&x| x.to_bytes())
-        .collect();
-        v.push(element);
+        .flat_map(QM31::to_m31s)
+        .flat_map(|c| c.representative().to_le_bytes())
+        .collect()
+}
+
+// folds of every round: fold_step, and what is left for the last one
+fn round_folds(config: &FriConfig, log_domain_size: u32) -> Vec<u32> {
+    let mut remaining = log_domain_size - config.log_last_layer_size();
+    let mut folds = Vec::new();
+    while remaining > 0 {
+        let round = remaining.min(config.fold_step);
+        folds.push(round);
+        remaining -= round;
     }
-    v
+    folds
 }
 
-pub fn unchunkify(field: &FieldElement<M31>, data: &[u8]) -> Vec<FieldElement<M31>>
-{
-    data.chunks(16).map(|chunk| field.from_bytes(chunk)).collect()
+// distinct leaves, in order, holding the sorted queries once `shift` folds are done
+fn queried_leaves(queries: &[usize], shift: u32) -> Vec<usize> {
+    let mut leaves: Vec<usize> = queries.iter().map(|q| q >> shift).collect();
+    leaves.dedup();
+    leaves
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(try_from = "ProofJson", into = "ProofJson"))]
 pub struct Proof {
-    // trees : Vec<Vec<Option<Vec<u8>>>>
-    roots: Vec<Vec<u8>>,
-    branches: Vec<Vec<Vec<Vec<u8>>>>,
-    leaf_values: Vec<Vec<Vec<CirclePoint>>>,
-    final_values: Vec<CirclePoint>
+    // per round: the root of the layer, the values of every queried leaf and one
+    // decommitment for all of them
+    roots: Vec<Digest>,
+    decommitments: Vec<MultiProof<Digest>>,
+    leaf_values: Vec<Vec<Vec<FieldElement<QM31>>>>,
+    // the last layer in full, in bit reversed order
+    final_values: Vec<FieldElement<QM31>>,
+    // proof of work mixed into the transcript before the queries are drawn
+    pow_nonce: u64,
+    config: FriConfig
 }
 
-pub fn prove_low_degree(evaluations: &Vec<CirclePoint>) -> Proof {
-    let domain = folded_reverse_bit_order(&get_initial_domain_of_size(MODULUS, evaluations.len()));
-    let values = folded_reverse_bit_order(evaluations);
-    let leaves = Vec::new();
-    let trees = Vec::new();
-    let roots = Vec::new();
-    let rounds = log2((evaluations.len() as u32)/(BASE_CASE_SIZE as u32)) /FOLDS_PER_ROUND as usize;
-    print!("generating proof");
-    for i in 0..rounds{
-        leaves.push(values);
-        trees.push(merkelize(chunkify(&values)));
-        roots.push(trees[trees.len()-1][1].unwrap());
-        let fold_factor : u32 = 1; //wasn't able to translate fold_factor = E(get_challenges(b''.join(roots), M, 4))
-        (domain,values) = fold(values, fold_factor, domain);
-    }
-    let entropy= roots
-        .iter()
-        .chain(values.iter().map(|x| x.to_bytes()))
-        .flatten()
-        .cloned()
-        .collect();
-    let challenges = get_challenges(entropy, evaluations.len() as u32 >> FOLDS_PER_ROUND , NUM_CHALLENGES as usize);
-    let round_challenges = (0..rounds).map(|i| {
-        challenges.iter().map(|&c| c >> ((i as u32)*FOLDS_PER_ROUND)).collect()}).collect();
-    let mut branches = Vec::new();
-    for (r_challenges, tree) in round_challenges.iter().zip(trees.iter()) {
-        let mut round_branches = Vec::new();
-        for &c in r_challenges {
-            round_branches.push(get_branch(tree, c));
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProofDecodeError {
+    Version(u8),
+    Config(FriConfigError),
+    // the input ends before the proof does
+    UnexpectedEnd,
+    // bytes left once the proof is read
+    TrailingBytes(usize),
+    // a coordinate that is not a canonical M31 value
+    NonCanonical(u32),
+    // the lists of rounds differ in length, or the leaves of a round in size
+    Shape,
+    // a digest that is not DIGEST_SIZE bytes of hex (JSON only)
+    Digest,
+}
+
+impl std::fmt::Display for ProofDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid FRI proof: {:?}", self)
+    }
+}
+
+impl std::error::Error for ProofDecodeError {}
+
+// the M31 coordinates of a value, as u32s
+fn felt_to_u32s(value: &FieldElement<QM31>) -> [u32; 4] {
+    value.to_m31_array().map(|c| c.representative())
+}
+
+fn felt_from_u32s(coordinates: [u32; 4]) -> Result<FieldElement<QM31>, ProofDecodeError> {
+    if let Some(c) = coordinates.iter().find(|c| **c >= MODULUS) {
+        return Err(ProofDecodeError::NonCanonical(*c));
+    }
+    let [a, b, c, d] = coordinates.map(|c| FieldElement::<M31>::from(c as u64));
+    Ok(FieldElement::<QM31>::from_m31(a, b, c, d))
+}
+
+// reads the binary encoding, every read is checked against what is left of the input
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ProofDecodeError> {
+        if n > self.bytes.len() {
+            return Err(ProofDecodeError::UnexpectedEnd);
         }
-        branches.push(round_branches);
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
     }
-    let mut leaf_values = Vec::new();
-    for (leaf_list, r_challenges) in leaves.iter().zip(round_challenges.iter()) {
-        let mut round_leaf_values = Vec::new();
-        for &c in r_challenges {
-            let start = c * FOLD_SIZE_RATIO;
-            let end = (c + 1) * FOLD_SIZE_RATIO;
-            if start < leaf_list.len() && end <= leaf_list.len() {
-                round_leaf_values.push(leaf_list[start..end].to_vec()); 
-            } else {
-                round_leaf_values.push(Vec::new());
-            }
+
+    fn u8(&mut self) -> Result<u8, ProofDecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ProofDecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ProofDecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // a number of items of item_size bytes, which must all be in the input, so that no
+    // length read from it can make us allocate more than its size
+    fn count(&mut self, item_size: usize) -> Result<usize, ProofDecodeError> {
+        let n = self.u32()? as usize;
+        match n.checked_mul(item_size.max(1)) {
+            Some(size) if size <= self.bytes.len() => Ok(n),
+            _ => Err(ProofDecodeError::UnexpectedEnd),
         }
-        leaf_values.push(round_leaf_values);
     }
-    Proof { roots, branches, leaf_values, final_values: values }
+
+    fn felt(&mut self) -> Result<FieldElement<QM31>, ProofDecodeError> {
+        let mut coordinates = [0; 4];
+        for c in coordinates.iter_mut() {
+            *c = self.u32()?;
+        }
+        felt_from_u32s(coordinates)
+    }
+
+    fn felts(&mut self, n: usize) -> Result<Vec<FieldElement<QM31>>, ProofDecodeError> {
+        (0..n).map(|_| self.felt()).collect()
+    }
+
+    fn digests(&mut self, n: usize) -> Result<Vec<Digest>, ProofDecodeError> {
+        (0..n).map(|_| Ok(self.take(DIGEST_SIZE)?.try_into().unwrap())).collect()
+    }
 }
 
-pub fn verify_low_degree(proof: Proof) -> bool{
-    let roots = proof.roots;
-    let branches = proof.branches;
-    let leaf_values = proof.leaf_values;
-    let final_values = proof.final_values;
-    let M = MODULUS;
-    let len_evaluations = final_values.len() << (FOLDS_PER_ROUND as usize * roots.len());
-
-    let mut entropy = Vec::new();
-    for root in roots {
-        entropy.extend(root);
-    }
-    for &x in &final_values {
-        entropy.extend(x.to_bytes());
-    }
-    let challenges = get_challenges(&entropy, len_evaluations as u32>> FOLDS_PER_ROUND, NUM_CHALLENGES as usize);
-
-    for i in 0..roots.len(){
-        let fold_factor : u32 = 1; //wasn't able to translate fold_factor = E(get_challenges(b''.join(roots), M, 4))
-        let evaluation_size = len_evaluations >> (i * FOLDS_PER_ROUND as usize);
-        let positions: Vec<usize> = challenges.iter().flat_map(|&c| (c * FOLD_SIZE_RATIO..(c + 1) * FOLD_SIZE_RATIO).map(|x| x as usize)).collect();
-        let mut domain = Vec::new();
-        if i==0{
-            let domain_tmp = Vec::new();
-            for pos in positions{
-                domain_tmp.push(get_single_domain_value(M, evaluation_size, rbo_index_to_original(evaluation_size, pos)));
+const FELT_SIZE: usize = 16;
+
+impl Proof {
+    // bumped on any change of the encodings below
+    pub const VERSION: u8 = 1;
+
+    pub fn config(&self) -> &FriConfig {
+        &self.config
+    }
+
+    // the Merkle root of every round, the first one being the commitment to the evaluation
+    pub fn roots(&self) -> &[Digest] {
+        &self.roots
+    }
+
+    // the shape every encoding relies on: one root, decommitment and list of leaves per
+    // round, and leaves of one round of one size
+    fn check_shape(&self) -> Result<(), ProofDecodeError> {
+        let n_rounds = self.roots.len();
+        if self.decommitments.len() != n_rounds || self.leaf_values.len() != n_rounds {
+            return Err(ProofDecodeError::Shape);
+        }
+        for leaves in &self.leaf_values {
+            let leaf_size = leaves.first().map_or(0, Vec::len);
+            if leaves.iter().any(|l| l.len() != leaf_size) {
+                return Err(ProofDecodeError::Shape);
             }
-            domain = domain_tmp;
-        } else {
-            let domain_tmp = Vec::new();
-            for pos in positions{
-                domain_tmp.push(halve_single_domain_value(&get_single_domain_value(M, evaluation_size*2, rbo_index_to_original(evaluation_size*2, pos*2))));
+        }
+        Ok(())
+    }
+
+    // canonical binary encoding, all integers little endian:
+    // version (u8), config header (FriConfig::to_bytes), number of rounds (u32) and the
+    // roots, then for every round the values per leaf and the number of queried leaves (u32s)
+    // followed by every leaf's values, and the number of siblings of its decommitment (u32)
+    // followed by the siblings, then the size of the last layer (u32) and its values, and the
+    // pow nonce (u64)
+    // a value is its 4 M31 coordinates as u32s, a digest its DIGEST_SIZE bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // proofs are made by prove_low_degree or decoded, both of which have this shape
+        self.check_shape().expect("proofs are well formed");
+        let mut bytes = vec![Self::VERSION];
+        bytes.extend(self.config.to_bytes());
+        bytes.extend((self.roots.len() as u32).to_le_bytes());
+        bytes.extend(self.roots.concat());
+        for (decommitment, leaves) in self.decommitments.iter().zip(&self.leaf_values) {
+            bytes.extend((leaves.first().map_or(0, Vec::len) as u32).to_le_bytes());
+            bytes.extend((leaves.len() as u32).to_le_bytes());
+            for values in leaves {
+                bytes.extend(values.iter().flat_map(felt_to_u32s).flat_map(u32::to_le_bytes));
             }
-            domain = domain_tmp;
+            bytes.extend((decommitment.siblings.len() as u32).to_le_bytes());
+            bytes.extend(decommitment.siblings.concat());
+        }
+        bytes.extend((self.final_values.len() as u32).to_le_bytes());
+        bytes.extend(self.final_values.iter().flat_map(felt_to_u32s).flat_map(u32::to_le_bytes));
+        bytes.extend(self.pow_nonce.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProofDecodeError> {
+        let mut reader = Reader { bytes };
+        let version = reader.u8()?;
+        if version != Self::VERSION {
+            return Err(ProofDecodeError::Version(version));
         }
-        let flattened_values = leaf_values[i].iter().flat_map(|v| v.iter().cloned()).collect();
-        let (folded_values, _) = fold(flattened_values, fold_factor, domain);
-        let mut expected_values = Vec::new();
-        if i < roots.len()-1{
-            let mut expected_values_list = Vec::new();
-            for (j, &c) in challenges.iter().enumerate(){
-                let value = leaf_values[i+1][j][(c % FOLD_SIZE_RATIO) as usize];
-                expected_values_list.push(value);
+        let config = FriConfig::from_bytes(reader.take(FriConfig::HEADER_SIZE)?).map_err(ProofDecodeError::Config)?;
+        let n_rounds = reader.count(DIGEST_SIZE)?;
+        let roots = reader.digests(n_rounds)?;
+
+        let mut decommitments = Vec::with_capacity(n_rounds);
+        let mut leaf_values = Vec::with_capacity(n_rounds);
+        for _ in 0..n_rounds {
+            let leaf_size = reader.u32()? as usize;
+            let leaf_bytes = leaf_size.checked_mul(FELT_SIZE).ok_or(ProofDecodeError::UnexpectedEnd)?;
+            let n_leaves = reader.count(leaf_bytes)?;
+            let values = (0..n_leaves).map(|_| reader.felts(leaf_size)).collect::<Result<_, _>>()?;
+            let n_siblings = reader.count(DIGEST_SIZE)?;
+            let siblings = reader.digests(n_siblings)?;
+            leaf_values.push(values);
+            decommitments.push(MultiProof { siblings });
+        }
+        let n_final = reader.count(FELT_SIZE)?;
+        let final_values = reader.felts(n_final)?;
+        let pow_nonce = reader.u64()?;
+        if !reader.bytes.is_empty() {
+            return Err(ProofDecodeError::TrailingBytes(reader.bytes.len()));
+        }
+        Ok(Proof { roots, decommitments, leaf_values, final_values, pow_nonce, config })
+    }
+}
+
+// the JSON form of a proof: digests as hex, values as their 4 coordinates
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct ProofJson {
+    version: u8,
+    config: FriConfig,
+    roots: Vec<String>,
+    decommitments: Vec<Vec<String>>,
+    leaf_values: Vec<Vec<Vec<[u32; 4]>>>,
+    final_values: Vec<[u32; 4]>,
+    pow_nonce: u64,
+}
+
+#[cfg(feature = "serde")]
+impl From<Proof> for ProofJson {
+    fn from(proof: Proof) -> Self {
+        let felts = |values: &[FieldElement<QM31>]| values.iter().map(felt_to_u32s).collect();
+        ProofJson {
+            version: Proof::VERSION,
+            config: proof.config,
+            roots: proof.roots.iter().map(hex::encode).collect(),
+            decommitments: proof
+                .decommitments
+                .iter()
+                .map(|d| d.siblings.iter().map(hex::encode).collect())
+                .collect(),
+            leaf_values: proof.leaf_values.iter().map(|round| round.iter().map(|l| felts(l)).collect()).collect(),
+            final_values: felts(&proof.final_values),
+            pow_nonce: proof.pow_nonce,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ProofJson> for Proof {
+    type Error = ProofDecodeError;
+
+    fn try_from(json: ProofJson) -> Result<Self, ProofDecodeError> {
+        if json.version != Proof::VERSION {
+            return Err(ProofDecodeError::Version(json.version));
+        }
+        json.config.validate().map_err(ProofDecodeError::Config)?;
+        let digest = |d: &String| -> Result<Digest, ProofDecodeError> {
+            let bytes = hex::decode(d).map_err(|_| ProofDecodeError::Digest)?;
+            bytes.try_into().map_err(|_| ProofDecodeError::Digest)
+        };
+        let felts = |values: &[[u32; 4]]| values.iter().map(|v| felt_from_u32s(*v)).collect();
+        let proof = Proof {
+            roots: json.roots.iter().map(digest).collect::<Result<_, _>>()?,
+            decommitments: json
+                .decommitments
+                .iter()
+                .map(|d| Ok(MultiProof { siblings: d.iter().map(digest).collect::<Result<_, _>>()? }))
+                .collect::<Result<_, _>>()?,
+            leaf_values: json
+                .leaf_values
+                .iter()
+                .map(|round| round.iter().map(|l| felts(l)).collect())
+                .collect::<Result<_, _>>()?,
+            final_values: felts(&json.final_values)?,
+            pow_nonce: json.pow_nonce,
+            config: json.config,
+        };
+        // the JSON form can hold what the binary one can't
+        proof.check_shape()?;
+        Ok(proof)
+    }
+}
+
+// proves that evaluation, over a canonic circle domain of size 2^log_size, is close to a
+// polynomial of 2^(log_size - log_blowup) coefficients (see CirclePoly)
+// the layers are kept in bit reversed order: the first fold takes the circle to the line of
+// its x coordinates, every later one halves the line, and all of them take neighbours
+// the fold coefficients are drawn from QM31, so layers after the first are over QM31
+// config.pow_bits of grinding make every attempt at a bad set of queries cost 2^pow_bits
+// hashes, which buys as much soundness as pow_bits / log_blowup extra queries
+pub fn prove_low_degree<F: ChannelField + IsSubFieldOf<QM31> + 'static>(evaluation: &CircleEvaluation<F>, config: &FriConfig) -> Proof
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    match config.hash {
+        FriHash::Sha256 => prove_with_hash::<Sha256Hash, F>(evaluation, config),
+        FriHash::Blake2s => prove_with_hash::<Blake2sHash, F>(evaluation, config),
+        FriHash::Blake3 => prove_with_hash::<Blake3Hash, F>(evaluation, config),
+    }
+}
+
+fn prove_with_hash<H: ChannelHash, F: ChannelField + IsSubFieldOf<QM31> + 'static>(
+    evaluation: &CircleEvaluation<F>,
+    config: &FriConfig,
+) -> Proof
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    config.validate().unwrap();
+    let domain = evaluation.domain();
+    assert!(domain.is_canonic());
+    let log_size = domain.log_size();
+    assert!(log_size > config.log_last_layer_size(), "domain is not larger than the last layer");
+
+    let mut values: Vec<FieldElement<QM31>> = evaluation
+        .values()
+        .iter()
+        .map(|v| v.clone().to_extension())
+        .collect();
+    bit_reverse(&mut values);
+
+    let mut channel = HashChannel::<H>::new();
+    channel.mix_bytes(&config.to_bytes());
+    let folds = round_folds(config, log_size);
+    let mut layers = Vec::new();
+    let mut trees = Vec::new();
+    let mut roots = Vec::new();
+    for (round, round_folds) in folds.iter().enumerate() {
+        let leaves: Vec<Vec<u8>> = values.chunks(1 << round_folds).map(leaf_bytes).collect();
+        let tree = MerkleTree::<HashMerkleHasher<H>>::new(&leaves);
+        let root = tree.root();
+        channel.mix_bytes(&root);
+        let fold_factor: FieldElement<QM31> = channel.draw_felt();
+        let folded = fold::<QM31>(&values, &fold_factor, round == 0, *round_folds);
+        layers.push(values);
+        trees.push(tree);
+        roots.push(root);
+        values = folded;
+    }
+    channel.mix_felts(&values);
+    let pow_nonce = channel.grind(config.pow_bits);
+    let mut queries = channel.draw_queries(log_size, config.n_queries);
+    queries.sort();
+
+    let mut decommitments = Vec::new();
+    let mut leaf_values = Vec::new();
+    let mut shift = 0;
+    for ((round_folds, layer), tree) in folds.iter().zip(&layers).zip(&trees) {
+        shift += round_folds;
+        let leaves = queried_leaves(&queries, shift);
+        decommitments.push(tree.open_many(&leaves));
+        leaf_values.push(
+            leaves
+                .iter()
+                .map(|leaf| layer[leaf << round_folds..(leaf + 1) << round_folds].to_vec())
+                .collect(),
+        );
+    }
+    Proof { roots, decommitments, leaf_values, final_values: values, pow_nonce, config: *config }
+}
+
+// config is the verifier's own, a proof made with any other parameters is rejected, and so
+// is one whose shape doesn't match the config and domain size
+pub fn verify_low_degree(proof: &Proof, config: &FriConfig, log_domain_size: u32) -> bool {
+    match config.hash {
+        FriHash::Sha256 => verify_with_hash::<Sha256Hash>(proof, config, log_domain_size),
+        FriHash::Blake2s => verify_with_hash::<Blake2sHash>(proof, config, log_domain_size),
+        FriHash::Blake3 => verify_with_hash::<Blake3Hash>(proof, config, log_domain_size),
+    }
+}
+
+fn verify_with_hash<H: ChannelHash>(proof: &Proof, config: &FriConfig, log_domain_size: u32) -> bool {
+    if proof.config != *config || config.validate().is_err() {
+        return false;
+    }
+    if log_domain_size <= config.log_last_layer_size() || log_domain_size > 31 {
+        return false;
+    }
+    let folds = round_folds(config, log_domain_size);
+    let n_rounds = folds.len();
+    if proof.roots.len() != n_rounds
+        || proof.decommitments.len() != n_rounds
+        || proof.leaf_values.len() != n_rounds
+        || proof.final_values.len() != 1 << config.log_last_layer_size()
+    {
+        return false;
+    }
+
+    // replay the prover's transcript
+    let mut channel = HashChannel::<H>::new();
+    channel.mix_bytes(&config.to_bytes());
+    let mut fold_factors: Vec<FieldElement<QM31>> = Vec::new();
+    for root in &proof.roots {
+        channel.mix_bytes(root);
+        fold_factors.push(channel.draw_felt());
+    }
+    channel.mix_felts(&proof.final_values);
+    if !channel.verify_pow(config.pow_bits, proof.pow_nonce) {
+        return false;
+    }
+    let mut queries = channel.draw_queries(log_domain_size, config.n_queries);
+    queries.sort();
+
+    // the queried leaves are in the committed layer, hold the values the previous round
+    // folded into, and fold into the values of the next layer
+    let mut layer_log_size = log_domain_size;
+    let mut shift = 0;
+    let mut folded: Vec<(usize, FieldElement<QM31>)> = Vec::new();
+    for (round, round_folds) in folds.iter().enumerate() {
+        shift += round_folds;
+        let leaves = queried_leaves(&queries, shift);
+        let leaf_values = &proof.leaf_values[round];
+        if leaf_values.len() != leaves.len() || leaf_values.iter().any(|v| v.len() != 1 << round_folds) {
+            return false;
+        }
+        let height = (layer_log_size - round_folds) as usize;
+        let opened: Vec<Vec<u8>> = leaf_values.iter().map(|v| leaf_bytes(v)).collect();
+        if !MerkleTree::<HashMerkleHasher<H>>::verify_many(
+            &proof.roots[round],
+            height,
+            &leaves,
+            &opened,
+            &proof.decommitments[round],
+        ) {
+            return false;
+        }
+        for (position, value) in &folded {
+            // every folded position is in one of this round's leaves
+            let leaf = leaves.binary_search(&(position >> round_folds)).unwrap();
+            if leaf_values[leaf][position & ((1 << round_folds) - 1)] != *value {
+                return false;
             }
-            expected_values = expected_values_list;
-        } else {
-            let mut expected_values_list = Vec::new();
-            for c in challenges{
-                expected_values_list.push(final_values[c as usize]);
+        }
+
+        folded = leaves
+            .iter()
+            .zip(leaf_values)
+            .map(|(leaf, values)| {
+                let positions: Vec<usize> = (leaf << round_folds..(leaf + 1) << round_folds).collect();
+                let value = fold_with_positions(
+                    values,
+                    layer_log_size,
+                    &positions,
+                    &fold_factors[round],
+                    round == 0,
+                    *round_folds,
+                );
+                (*leaf, value[0])
+            })
+            .collect();
+        layer_log_size -= round_folds;
+    }
+    if folded.iter().any(|(position, value)| proof.final_values[*position] != *value) {
+        return false;
+    }
+
+    // and the last layer is a polynomial of 2^log_last_layer_degree_bound coefficients
+    let mut coeffs = proof.final_values.clone();
+    line_fft(&mut coeffs, &TwiddleTree::cached(config.log_last_layer_size() + 1));
+    coeffs[1 << config.log_last_layer_degree_bound..]
+        .iter()
+        .all(|c| *c == FieldElement::zero())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::CanonicCoset;
+    use crate::poly::CirclePoly;
+    use crate::utils::random_values;
+
+    // evaluation over the canonic domain of size 2^log_size of a random polynomial of
+    // 2^log_coeffs coefficients
+    fn low_degree_evaluation(log_coeffs: u32, log_size: u32, seed: u64) -> CircleEvaluation {
+        let poly = CirclePoly::new(random_values(1 << log_coeffs, seed));
+        poly.extend(log_size).evaluate(CanonicCoset::new(log_size).circle_domain())
+    }
+
+    #[test]
+    fn config_is_validated_and_round_trips_through_the_header() {
+        let config = FriConfig::new(2, 5, 3, 40, 16).unwrap();
+        assert_eq!(FriConfig::from_bytes(&config.to_bytes()), Ok(config));
+        assert_eq!(FriConfig::from_bytes(&FriConfig::default().to_bytes()), Ok(FriConfig::default()));
+        let blake3 = config.with_hash(FriHash::Blake3);
+        assert_eq!(FriConfig::from_bytes(&blake3.to_bytes()), Ok(blake3));
+
+        assert_eq!(FriConfig::new(0, 5, 3, 40, 16), Err(FriConfigError::LogBlowup(0)));
+        assert_eq!(FriConfig::new(2, 5, 0, 40, 16), Err(FriConfigError::FoldStep(0)));
+        assert_eq!(FriConfig::new(2, 5, 3, 0, 16), Err(FriConfigError::NQueries(0)));
+        assert_eq!(FriConfig::new(2, 5, 3, 40, 65), Err(FriConfigError::PowBits(65)));
+        assert_eq!(FriConfig::new(2, 21, 3, 40, 16), Err(FriConfigError::LogLastLayerDegreeBound(21)));
+
+        let mut header = config.to_bytes();
+        header[2] = 9;
+        assert_eq!(FriConfig::from_bytes(&header), Err(FriConfigError::FoldStep(9)));
+        let mut header = config.to_bytes();
+        header[4] = 7;
+        assert_eq!(FriConfig::from_bytes(&header), Err(FriConfigError::Hash(7)));
+        assert_eq!(FriConfig::from_bytes(&header[..7]), Err(FriConfigError::HeaderLength(7)));
+    }
+
+    #[test]
+    fn security_bits() {
+        let config = FriConfig::new(2, 5, 3, 40, 16).unwrap();
+        assert_eq!(config.conjectured_security_bits(), 96);
+        assert_eq!(config.proven_security_bits(20), 56);
+        // large domains are bounded by the commit phase instead
+        assert_eq!(config.proven_security_bits(40), SECURE_FIELD_BITS - 80);
+        // capped by the challenge field
+        let config = FriConfig::new(4, 5, 3, 100, 20).unwrap();
+        assert_eq!(config.conjectured_security_bits(), SECURE_FIELD_BITS);
+    }
+
+    #[test]
+    fn the_first_root_commits_to_the_evaluation() {
+        let config = FriConfig::new(1, 2, 3, 20, 4).unwrap();
+        let log_size = 12;
+        let evaluation = low_degree_evaluation(log_size - 1, log_size, 1);
+        let proof = prove_low_degree(&evaluation, &config);
+        assert_eq!(proof.roots().len(), round_folds(&config, log_size).len());
+
+        let mut values: Vec<FieldElement<QM31>> =
+            evaluation.values().iter().map(|v| v.to_extension()).collect();
+        bit_reverse(&mut values);
+        let leaves: Vec<Vec<u8>> = values.chunks(1 << config.fold_step).map(leaf_bytes).collect();
+        let tree = MerkleTree::<HashMerkleHasher<Sha256Hash>>::new(&leaves);
+        assert_eq!(proof.roots()[0], tree.root());
+
+        let other = prove_low_degree(&low_degree_evaluation(log_size - 1, log_size, 2), &config);
+        assert_ne!(other.roots()[0], proof.roots()[0]);
+    }
+
+    #[test]
+    fn honest_proofs_verify() {
+        // fold steps that divide the folds, and ones that leave a smaller last round
+        for (fold_step, log_size) in [(3, 12), (4, 12), (2, 7)] {
+            let config = FriConfig::new(1, 2, fold_step, 20, 4).unwrap();
+            let evaluation = low_degree_evaluation(log_size - 1, log_size, log_size as u64);
+            let proof = prove_low_degree(&evaluation, &config);
+            assert!(verify_low_degree(&proof, &config, log_size));
+        }
+
+        // and over QM31
+        let config = FriConfig::new(2, 1, 3, 10, 4).unwrap();
+        let log_size = 10;
+        let columns: Vec<_> = (0..4).map(|i| low_degree_evaluation(8, log_size, i)).collect();
+        let values = (0..1 << log_size)
+            .map(|i| QM31::from_m31s(&columns.iter().map(|c| c.values()[i]).collect::<Vec<_>>()))
+            .collect();
+        let evaluation = CircleEvaluation::new(CanonicCoset::new(log_size).circle_domain(), values);
+        let proof = prove_low_degree(&evaluation, &config);
+        assert!(verify_low_degree(&proof, &config, log_size));
+    }
+
+    #[test]
+    fn every_hash_proves_and_verifies() {
+        let log_size = 10;
+        let evaluation = low_degree_evaluation(log_size - 1, log_size, 5);
+        let mut roots = Vec::new();
+        for hash in [FriHash::Sha256, FriHash::Blake2s, FriHash::Blake3] {
+            let config = FriConfig::new(1, 2, 3, 20, 4).unwrap().with_hash(hash);
+            let proof = prove_low_degree(&evaluation, &config);
+            assert!(verify_low_degree(&proof, &config, log_size));
+            // the roots and nonce don't hold up under another hash
+            let other = if hash == FriHash::Sha256 { FriHash::Blake3 } else { FriHash::Sha256 };
+            let mut relabeled = proof.clone();
+            relabeled.config = config.with_hash(other);
+            assert!(!verify_low_degree(&proof, &relabeled.config, log_size));
+            assert!(!verify_low_degree(&relabeled, &relabeled.config, log_size));
+            roots.push(proof.roots[0]);
+        }
+        assert!(roots[0] != roots[1] && roots[1] != roots[2] && roots[0] != roots[2]);
+    }
+
+    #[test]
+    fn decommitments_are_smaller_than_paths() {
+        let config = FriConfig::new(1, 2, 3, 80, 4).unwrap();
+        let log_size = 14;
+        let proof = prove_low_degree(&low_degree_evaluation(log_size - 1, log_size, 6), &config);
+        assert!(verify_low_degree(&proof, &config, log_size));
+        // a path per leaf: the first tree has 2^(log_size - 3) leaves
+        let paths = proof.leaf_values[0].len() * (log_size as usize - 3);
+        assert!(proof.decommitments[0].siblings.len() * 4 < paths * 3);
+    }
+
+    #[test]
+    fn perturbed_proofs_are_rejected() {
+        let config = FriConfig::new(1, 2, 3, 20, 4).unwrap();
+        let log_size = 11;
+        let evaluation = low_degree_evaluation(log_size - 1, log_size, 1);
+        let proof = prove_low_degree(&evaluation, &config);
+        assert!(verify_low_degree(&proof, &config, log_size));
+        assert!(!verify_low_degree(&proof, &config, log_size + 1));
+        let other = FriConfig::new(1, 2, 3, 21, 4).unwrap();
+        assert!(!verify_low_degree(&proof, &other, log_size));
+
+        let one = FieldElement::<QM31>::one();
+        let mut perturbed = Vec::new();
+        for round in 0..proof.roots.len() {
+            let mut bad = proof.clone();
+            bad.roots[round][0] ^= 1;
+            perturbed.push(bad);
+            let mut bad = proof.clone();
+            bad.leaf_values[round][0][1] += one;
+            perturbed.push(bad);
+            // the last rounds may have all their leaves queried, and no siblings
+            if !proof.decommitments[round].siblings.is_empty() {
+                let mut bad = proof.clone();
+                bad.decommitments[round].siblings[0][0] ^= 1;
+                perturbed.push(bad);
+                let mut bad = proof.clone();
+                bad.decommitments[round].siblings.pop();
+                perturbed.push(bad);
             }
-            expected_values = expected_values_list;
+            let mut bad = proof.clone();
+            bad.decommitments[round].siblings.push(proof.roots[round]);
+            perturbed.push(bad);
         }
-        assert!(folded_values == expected_values);
-        for (j, &c) in challenges.iter().enumerate(){
-            assert!(verify_branch(roots[i], c as i32, chunkify(&leaf_values[i][j].to_vec())[0], branches[i][j]));
+        let mut bad = proof.clone();
+        bad.final_values[0] += one;
+        perturbed.push(bad);
+        let mut bad = proof.clone();
+        bad.pow_nonce += 1;
+        perturbed.push(bad);
+        for bad in perturbed {
+            assert!(!verify_low_degree(&bad, &config, log_size));
         }
-        let mut challenges_new = Vec::new();
-        for c in challenges{
-            challenges_new.push(c >> FOLDS_PER_ROUND);
+    }
+
+    #[test]
+    fn high_degree_evaluations_are_rejected() {
+        let config = FriConfig::new(2, 2, 3, 20, 4).unwrap();
+        let log_size = 11;
+        // twice the degree the blowup allows
+        let evaluation = low_degree_evaluation(log_size - 1, log_size, 2);
+        let proof = prove_low_degree(&evaluation, &config);
+        assert!(!verify_low_degree(&proof, &config, log_size));
+
+        let values = random_values(1 << log_size, 3);
+        let evaluation = CircleEvaluation::new(CanonicCoset::new(log_size).circle_domain(), values);
+        let proof = prove_low_degree(&evaluation, &config);
+        assert!(!verify_low_degree(&proof, &config, log_size));
+    }
+
+    fn small_proof() -> (Proof, FriConfig, u32) {
+        let config = FriConfig::new(1, 2, 3, 20, 4).unwrap();
+        let log_size = 9;
+        let proof = prove_low_degree(&low_degree_evaluation(log_size - 1, log_size, 4), &config);
+        (proof, config, log_size)
+    }
+
+    #[test]
+    fn binary_encoding_round_trips() {
+        let (proof, config, log_size) = small_proof();
+        let bytes = proof.to_bytes();
+        let decoded = Proof::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert!(verify_low_degree(&decoded, &config, log_size));
+    }
+
+    #[test]
+    fn malformed_binary_proofs_are_rejected() {
+        let (proof, _, _) = small_proof();
+        let bytes = proof.to_bytes();
+        for len in 0..bytes.len() {
+            assert_eq!(Proof::from_bytes(&bytes[..len]), Err(ProofDecodeError::UnexpectedEnd));
         }
-        challenges = challenges_new;
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(Proof::from_bytes(&longer), Err(ProofDecodeError::TrailingBytes(1)));
+
+        let mut bad = bytes.clone();
+        bad[0] = Proof::VERSION + 1;
+        assert_eq!(Proof::from_bytes(&bad), Err(ProofDecodeError::Version(Proof::VERSION + 1)));
+        let mut bad = bytes.clone();
+        bad[3] = 0;
+        assert_eq!(Proof::from_bytes(&bad), Err(ProofDecodeError::Config(FriConfigError::FoldStep(0))));
+        let mut bad = bytes.clone();
+        bad[5] = 3;
+        assert_eq!(Proof::from_bytes(&bad), Err(ProofDecodeError::Config(FriConfigError::Hash(3))));
+        // a number of rounds far past the end of the input
+        let mut bad = bytes.clone();
+        bad[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(Proof::from_bytes(&bad), Err(ProofDecodeError::UnexpectedEnd));
+        // the last coordinate of the last value of the last layer, right before the nonce
+        let mut bad = bytes.clone();
+        let end = bad.len() - 8;
+        bad[end - 4..end].copy_from_slice(&MODULUS.to_le_bytes());
+        assert_eq!(Proof::from_bytes(&bad), Err(ProofDecodeError::NonCanonical(MODULUS)));
     }
-    let final_domain = folded_reverse_bit_order(&halve_domain(&get_initial_domain_of_size(M, final_values.len()*2), true));
-    assert!(is_rbo_low_degree(&final_values, &final_domain));
-    return true;
-}
\ No newline at end of file
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_encoding_round_trips() {
+        let (proof, config, log_size) = small_proof();
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: Proof = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(verify_low_degree(&decoded, &config, log_size));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn malformed_json_proofs_are_rejected() {
+        let (proof, _, _) = small_proof();
+        let json = serde_json::to_value(&proof).unwrap();
+        let rejects = |edit: &dyn Fn(&mut serde_json::Value)| {
+            let mut bad = json.clone();
+            edit(&mut bad);
+            serde_json::from_value::<Proof>(bad).is_err()
+        };
+        assert!(rejects(&|p| p["version"] = (Proof::VERSION + 1).into()));
+        assert!(rejects(&|p| p["config"]["fold_step"] = 0.into()));
+        assert!(rejects(&|p| p["roots"][0] = "not hex".into()));
+        assert!(rejects(&|p| p["final_values"][0][0] = MODULUS.into()));
+        assert!(rejects(&|p| {
+            p["roots"].as_array_mut().unwrap().pop();
+        }));
+        assert!(rejects(&|p| {
+            p["leaf_values"][0][0].as_array_mut().unwrap().pop();
+        }));
+        assert!(rejects(&|p| p["decommitments"][0][0] = "00".into()));
+        assert!(!rejects(&|_| ()));
+    }
+}
@@ -1,13 +1,18 @@
-mod circle;
-mod fast_fft;
-mod fast_fri;
-mod fft;
-mod fri;
-mod line_functions;
-mod merkle;
-mod precomputes;
-mod utils;
-mod poseidon;
-fn main() {
-    println!("Hello, world!");
-}
+#![allow(non_snake_case)]
+
+pub mod channel;
+pub mod circle;
+pub mod cm31;
+pub mod domain;
+pub mod fast_fft;
+pub mod fast_fri;
+pub mod fft;
+pub mod fri;
+pub mod line_functions;
+pub mod merkle;
+pub mod packed;
+pub mod poly;
+pub mod precomputes;
+pub mod qm31;
+pub mod utils;
+pub mod poseidon;
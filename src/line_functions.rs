@@ -2,13 +2,15 @@ use lambdaworks_math::field::element::FieldElement;
 use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
 //use ndarray at last maybe
 use crate::circle::{CircleImpl, CirclePoint};
-// use crate::fast_fft::bary_eval;
-use crate::precomputes::get_subdomains;
+use crate::domain::CanonicCoset;
+use crate::fast_fft::bary_eval;
+use crate::precomputes::TwiddleTree;
+use crate::utils::{batch_inverse, bit_reverse};
 
 pub fn line_function(
     p1: CirclePoint,
     p2: CirclePoint,
-    domain: &Vec<CirclePoint>,
+    domain: &[CirclePoint],
 ) -> Vec<FieldElement<M31>> {
     let a = p2.get_y() - p1.get_y();
     let b = p1.get_x() - p2.get_x();
@@ -17,126 +19,225 @@ pub fn line_function(
         .iter()
         .map(|d| a * d.get_x() + b * d.get_y() + c)
         .collect();
-    return res;
+    res
 }
 
+// the linear function of (x, y) that is v1 at p1 and v2 at p2, over domain
 pub fn interpolant(
     p1: CirclePoint,
     p2: CirclePoint,
-    v1: &[Vec<FieldElement<M31>>],
-    v2: &[Vec<FieldElement<M31>>],
+    v1: FieldElement<M31>,
+    v2: FieldElement<M31>,
     domain: &[CirclePoint],
-) -> Vec<Vec<FieldElement<M31>>> {
-    let mut result = Vec::with_capacity(v1.len());
-
-    for j in 0..v1[0].len() {
-        let mut column_result = Vec::with_capacity(domain.len()); // format is different take care
-
-        for i in 0..domain.len() {
-            let dx = p2.get_x() - p1.get_x();
-            let dy = p2.get_y() - p1.get_y();
-
-            // Compute inverse distance (simplified for demonstration)
-            let invdist = (dx * dx + dy * dy).inv().unwrap();
-
-            let dot = (domain[i].get_x() - p1.get_x()) * dx + (domain[i].get_y() - p1.get_y()) * dy;
-
-            let interpolated_val = v1[0][j] + (v2[0][j] - v1[0][j]) * dot * invdist;
-
-            column_result.push(interpolated_val);
-        }
-        result.push(column_result);
-    }
+) -> Vec<FieldElement<M31>> {
+    let dx = p2.get_x() - p1.get_x();
+    let dy = p2.get_y() - p1.get_y();
+    // -1 is not a square in M31, so dx^2 + dy^2 = 0 only if p1 == p2
+    let invdist = (dx * dx + dy * dy).inv().unwrap();
+    domain
+        .iter()
+        .map(|d| {
+            let dot = (d.get_x() - p1.get_x()) * dx + (d.get_y() - p1.get_y()) * dy;
+            v1 + (v2 - v1) * dot * invdist
+        })
+        .collect()
+}
 
-    result
+#[derive(Debug, PartialEq, Eq)]
+pub enum PublicArgsError {
+    // no public input points to vanish on
+    Empty,
+    // the points are interpolated in pairs, so there must be an even number of them
+    OddLength(usize),
+    // the trace domain is a canonic domain, its size must be a power of two
+    DomainSizeNotPowerOfTwo(u32),
+    // the canonic domain holding the points is not smaller than the trace domain of this size
+    DomainTooSmall(u32),
+    // an index past the trace domain
+    IndexOutOfRange(u32),
+    // two public inputs at the same trace point
+    RepeatedIndex(u32),
+    // vals doesn't have one row per index, but this many
+    ValsLength(usize),
+    // this row of vals doesn't have as many columns as the first one
+    RaggedRow(usize),
 }
 
+// (vanishing polynomial, interpolant): one value, and one row of column values, per point
+pub type VanishAndInterp = (Vec<FieldElement<M31>>, Vec<Vec<FieldElement<M31>>>);
+
+// vanishing polynomial of the public input points and the interpolant of the public values,
+// evaluated over a canonic domain large enough for the vanishing polynomial
+// (plus out_domain if given, in which case only the values there are returned)
+// vals[i] holds the value of every column at the trace point indices[i]
 pub fn public_args_to_vanish_and_interp(
     domain_size: u32, // might be usize
     indices: &[u32],
     vals: &[Vec<FieldElement<M31>>],
     out_domain: Option<CirclePoint>,
-) -> (Vec<FieldElement<M31>>, Vec<Vec<FieldElement<M31>>>) {
-    assert!(indices.len() % 2 == 0, "Indices array has odd length");
-    let next_power_of_2 = 2usize.pow((indices.len() - 1).ilog2() + 1) as u32;
-    assert!(next_power_of_2 < domain_size, "domain size is large");
-
-    let mut lines: Vec<FieldElement<M31>> = Vec::new();
+) -> Result<VanishAndInterp, PublicArgsError> {
+    if indices.is_empty() {
+        return Err(PublicArgsError::Empty);
+    }
+    if !indices.len().is_multiple_of(2) {
+        return Err(PublicArgsError::OddLength(indices.len()));
+    }
+    if vals.len() != indices.len() {
+        return Err(PublicArgsError::ValsLength(vals.len()));
+    }
+    if let Some(row) = vals.iter().position(|row| row.len() != vals[0].len()) {
+        return Err(PublicArgsError::RaggedRow(row));
+    }
+    if !domain_size.is_power_of_two() {
+        return Err(PublicArgsError::DomainSizeNotPowerOfTwo(domain_size));
+    }
+    let log_size = (indices.len() - 1).ilog2() + 1;
+    let next_power_of_2 = 1usize << log_size;
+    if next_power_of_2 >= domain_size as usize {
+        return Err(PublicArgsError::DomainTooSmall(domain_size));
+    }
+    if let Some(index) = indices.iter().find(|i| **i >= domain_size) {
+        return Err(PublicArgsError::IndexOutOfRange(*index));
+    }
+    let mut sorted = indices.to_vec();
+    sorted.sort();
+    if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(PublicArgsError::RepeatedIndex(pair[0]));
+    }
 
-    let sub_domains = get_subdomains();
-    let mut eval_domain: Vec<CirclePoint> = sub_domains
-        .iter()
-        .skip(next_power_of_2 as usize)
-        .take(next_power_of_2 as usize)
-        .cloned()
-        .collect();
+    let eval_domain = CanonicCoset::new(log_size).circle_domain();
+    let twiddles = TwiddleTree::cached(log_size);
+    let mut eval_points: Vec<CirclePoint> = eval_domain.iter().collect();
     if let Some(out_domain) = out_domain {
-        eval_domain.push(out_domain);
+        eval_points.push(out_domain);
     }
 
-    // Initialize vpoly
-    let mut vpoly: Vec<FieldElement<M31>> = vec![FieldElement::new(1); eval_domain.len()];
-
+    let trace_domain = CanonicCoset::new(domain_size.ilog2()).circle_domain();
     let points: Vec<CirclePoint> = indices
         .iter()
-        .map(|i| sub_domains[(domain_size + i) as usize])
+        .map(|i| trace_domain.at(*i as usize))
         .collect();
 
-    for i in (0..indices.len()).step_by(2) {
-        let mut line = line_function(points[i], points[i + 1], &eval_domain); // this might be that we only need a single line , oonstead of all the lines
-        lines.append(&mut line);
-
-        vpoly = vpoly
-            .iter()
-            .zip(lines.clone())
-            .map(|(v, l)| FieldElement::<M31>::new(v.to_raw() * l.to_raw()))
-            .collect();
-    }
-
-    // Initialize interpolation
-    let mut interp = vec![vec![FieldElement::new(0); vals[0].len()]; eval_domain.len()];
-    for i in (0..indices.len()).step_by(2) {
-        let mut vpoly_adjusted = Vec::with_capacity(eval_domain.len());
-        for j in 0..eval_domain.len() {
-            // Divide vpoly by corresponding line (simplified)
-            let divided_val = vpoly[j] / (lines[i / 2]);
-            vpoly_adjusted.push(divided_val);
+    // one line through each pair of points, the vanishing polynomial is their product
+    let lines: Vec<Vec<FieldElement<M31>>> = points
+        .chunks(2)
+        .map(|pair| line_function(pair[0], pair[1], &eval_points))
+        .collect();
+    let mut vpoly: Vec<FieldElement<M31>> = vec![FieldElement::one(); eval_points.len()];
+    for line in &lines {
+        vpoly = vpoly.iter().zip(line).map(|(v, l)| v * l).collect();
+    }
+
+    // product of the other lines for every pair, it vanishes on every other pair so the
+    // interpolant only has to fix this one. Multiplied out instead of vpoly / line, which is
+    // 0/0 wherever this line vanishes
+    let mut vpolys_adjusted = Vec::with_capacity(lines.len());
+    let mut ys = Vec::with_capacity(points.len());
+    for pair in 0..lines.len() {
+        let mut vpoly_adjusted: Vec<FieldElement<M31>> =
+            vec![FieldElement::one(); eval_points.len()];
+        for (_, line) in lines.iter().enumerate().filter(|(other, _)| *other != pair) {
+            vpoly_adjusted = vpoly_adjusted.iter().zip(line).map(|(v, l)| v * l).collect();
         }
 
-        let y1 = FieldElement::new(1); //bary_eval
-        let y2 = FieldElement::new(1); // bary_eval needs to be replaced
-
-        // Compute interpolant
-        let interpolant_vals = interpolant(
-            points[i],
-            points[i + 1],
-            &[vec![vals[i][0] / (&y1)]],
-            &[vec![vals[i + 1][0] / (&y2)]],
-            &eval_domain,
-        );
-
-        // Update interpolation
-        for j in 0..eval_domain.len() {
-            for k in 0..vals[0].len() {
-                // Multiply vpoly_adjusted by interpolant
-                let mul_val = vpoly_adjusted[j] * interpolant_vals[0][j];
-                interp[j][k] = interp[j][k] + mul_val;
+        let mut on_domain = vpoly_adjusted[..next_power_of_2].to_vec();
+        bit_reverse(&mut on_domain);
+        ys.push(bary_eval(&on_domain, &points[2 * pair], &twiddles));
+        ys.push(bary_eval(&on_domain, &points[2 * pair + 1], &twiddles));
+        vpolys_adjusted.push(vpoly_adjusted);
+    }
+    // the other lines don't vanish on this pair (the points are distinct)
+    let inv_ys = batch_inverse(&ys).expect("distinct points are on distinct lines");
+
+    let mut interp = vec![vec![FieldElement::zero(); vals[0].len()]; eval_points.len()];
+    for (pair, vpoly_adjusted) in vpolys_adjusted.iter().enumerate() {
+        let (i, j) = (2 * pair, 2 * pair + 1);
+        for k in 0..vals[0].len() {
+            let column = interpolant(
+                points[i],
+                points[j],
+                vals[i][k] * inv_ys[i],
+                vals[j][k] * inv_ys[j],
+                &eval_points,
+            );
+            for (row, (v, c)) in interp.iter_mut().zip(vpoly_adjusted.iter().zip(column)) {
+                row[k] += v * c;
             }
         }
     }
     if out_domain.is_some() {
-        return (
-            vpoly
-                .iter()
-                .skip(next_power_of_2 as usize)
-                .cloned()
-                .collect(),
-            interp
-                .iter()
-                .skip(next_power_of_2 as usize)
-                .cloned()
-                .collect(),
-        );
-    }
-    return (vpoly, interp);
+        return Ok((
+            vpoly.iter().skip(next_power_of_2).cloned().collect(),
+            interp.iter().skip(next_power_of_2).cloned().collect(),
+        ));
+    }
+    Ok((vpoly, interp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::random_values;
+
+    // three columns of values at every index
+    fn public_vals(indices: &[u32]) -> Vec<Vec<FieldElement<M31>>> {
+        random_values(3 * indices.len(), indices.len() as u64)
+            .chunks(3)
+            .map(|c| c.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn interpolant_takes_the_values_where_the_vanishing_polynomial_vanishes() {
+        let trace_domain = CanonicCoset::new(6).circle_domain();
+        for indices in [vec![0, 1], vec![3, 17, 40, 63], vec![5, 9, 2, 33, 60, 11]] {
+            let vals = public_vals(&indices);
+            for (index, expected) in indices.iter().zip(&vals) {
+                let point = trace_domain.at(*index as usize);
+                let (vpoly, interp) =
+                    public_args_to_vanish_and_interp(64, &indices, &vals, Some(point)).unwrap();
+                assert_eq!(vpoly, vec![FieldElement::zero()]);
+                assert_eq!(interp, vec![expected.clone()]);
+            }
+        }
+    }
+
+    #[test]
+    fn out_domain_matches_the_eval_domain() {
+        let indices = [3, 17, 40, 63, 8, 21];
+        let vals = public_vals(&indices);
+        let (vpoly, interp) = public_args_to_vanish_and_interp(64, &indices, &vals, None).unwrap();
+        let eval_domain = CanonicCoset::new(3).circle_domain();
+        assert_eq!((vpoly.len(), interp.len()), (eval_domain.size(), eval_domain.size()));
+        for (j, point) in eval_domain.iter().enumerate() {
+            let (v, i) = public_args_to_vanish_and_interp(64, &indices, &vals, Some(point)).unwrap();
+            assert_eq!(v, vec![vpoly[j]]);
+            assert_eq!(i, vec![interp[j].clone()]);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_public_args() {
+        let check = |domain_size, indices: &[u32]| {
+            public_args_to_vanish_and_interp(domain_size, indices, &public_vals(indices), None).err()
+        };
+        assert_eq!(check(64, &[]), Some(PublicArgsError::Empty));
+        assert_eq!(check(64, &[1, 2, 3]), Some(PublicArgsError::OddLength(3)));
+        assert_eq!(check(4, &[0, 1, 2, 3]), Some(PublicArgsError::DomainTooSmall(4)));
+        assert_eq!(check(64, &[1, 64]), Some(PublicArgsError::IndexOutOfRange(64)));
+        assert_eq!(check(64, &[7, 2, 9, 7]), Some(PublicArgsError::RepeatedIndex(7)));
+        assert_eq!(check(100, &[1, 70]), Some(PublicArgsError::DomainSizeNotPowerOfTwo(100)));
+        assert_eq!(check(0, &[1, 2]), Some(PublicArgsError::DomainSizeNotPowerOfTwo(0)));
+
+        let indices = [3, 17, 40, 63];
+        let vals = public_vals(&indices);
+        let check_vals = |vals: &[Vec<FieldElement<M31>>]| {
+            public_args_to_vanish_and_interp(64, &indices, vals, None).err()
+        };
+        assert_eq!(check_vals(&vals[..3]), Some(PublicArgsError::ValsLength(3)));
+        assert_eq!(check_vals(&[]), Some(PublicArgsError::ValsLength(0)));
+        let mut ragged = vals.clone();
+        ragged[2].pop();
+        assert_eq!(check_vals(&ragged), Some(PublicArgsError::RaggedRow(2)));
+    }
 }
@@ -1,48 +1,796 @@
-use sha256::digest;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use crate::channel::{Blake2sHash, Blake3Hash, ChannelField, ChannelHash, Sha256Hash};
+use crate::poseidon::poseidon_hash;
+use lambdaworks_math::field::element::FieldElement;
+use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use std::fmt::Debug;
+use std::marker::PhantomData;
 
-pub fn hash(x: Vec<u8>) -> Vec<u8> {
-    return digest(x).as_bytes().to_vec();
+// leaves and internal nodes are hashed behind different prefixes (as in RFC 6962), so that
+// no internal node can be passed off as a leaf or the other way around
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+// Leaf is what a leaf holds, bytes or field elements
+pub trait MerkleHasher {
+    type Leaf: ?Sized + Sync;
+    type Digest: Clone + Debug + PartialEq + Eq + Send + Sync;
+
+    fn hash_leaf(data: &Self::Leaf) -> Self::Digest;
+    fn hash_node(left: &Self::Digest, right: &Self::Digest) -> Self::Digest;
+}
+
+// H(0 || data) for leaves, H(1 || left || right) for nodes, with the hashes of the channel
+pub struct HashMerkleHasher<H: ChannelHash> {
+    _hash: PhantomData<H>,
+}
+
+pub type Sha256MerkleHasher = HashMerkleHasher<Sha256Hash>;
+pub type Blake2sMerkleHasher = HashMerkleHasher<Blake2sHash>;
+pub type Blake3MerkleHasher = HashMerkleHasher<Blake3Hash>;
+
+impl<H: ChannelHash> MerkleHasher for HashMerkleHasher<H> {
+    type Leaf = [u8];
+    type Digest = [u8; 32];
+
+    fn hash_leaf(data: &[u8]) -> [u8; 32] {
+        H::hash(&[&[LEAF_PREFIX], data].concat())
+    }
+
+    fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut input = [NODE_PREFIX; 65];
+        input[1..33].copy_from_slice(left);
+        input[33..].copy_from_slice(right);
+        H::hash(&input)
+    }
+}
+
+// over M31, so that the tree can be verified inside a circle STARK: nodes are
+// poseidon_hash(left, right), and a leaf is padded with a 1 and then zeros to a multiple of
+// 8 elements (as in PoseidonChannel) and absorbed 8 at a time by poseidon_hash into a digest
+// that starts at leaf_iv(). A leaf only hashes like a node whose left child is leaf_iv(), and
+// finding one takes a preimage of poseidon_hash
+pub struct PoseidonMerkleHasher;
+
+impl PoseidonMerkleHasher {
+    // [1, 0, ..., 0]
+    fn leaf_iv() -> [FieldElement<M31>; 8] {
+        let mut iv = [FieldElement::zero(); 8];
+        iv[0] = FieldElement::one();
+        iv
+    }
 }
 
-pub fn merkelize(vals: Vec<Vec<u8>>) -> Vec<Option<Vec<u8>>> {
-    assert!(vals.len() & (vals.len()-1) == 0);
-    let mut o = vec![None; vals.len()];
-    o.extend(vals.iter().map(|val| Some(digest(val).as_bytes().to_vec())));
-    for i in (0..vals.len()).rev() {
-        let o1 = o[i*2].clone().unwrap();
-        let s1 = o1.as_slice();
-        let o2 = o[i*2+1].clone().unwrap();
-        let s2 = o2.as_slice();
-        let result: Vec<u8> = s1.iter()
-        .zip(s2.iter())
-        .map(|(a, b)| a + b) // Add corresponding elements
-        .collect();
-        o[i] = Some(digest(result.as_slice()).as_bytes().to_vec());
+impl MerkleHasher for PoseidonMerkleHasher {
+    type Leaf = [FieldElement<M31>];
+    type Digest = [FieldElement<M31>; 8];
+
+    fn hash_leaf(data: &[FieldElement<M31>]) -> [FieldElement<M31>; 8] {
+        let mut padded = data.to_vec();
+        padded.push(FieldElement::one());
+        padded.resize(padded.len().next_multiple_of(8), FieldElement::zero());
+        padded
+            .chunks_exact(8)
+            .fold(Self::leaf_iv(), |digest, chunk| poseidon_hash(&digest, chunk.try_into().unwrap()))
+    }
+
+    fn hash_node(left: &[FieldElement<M31>; 8], right: &[FieldElement<M31>; 8]) -> [FieldElement<M31>; 8] {
+        poseidon_hash(left, right)
     }
-    o
 }
 
-pub fn get_root(tree: Vec<&[u8]>) -> Option<&[u8]> {
-    return Some(tree[1]);
+// layers[0] holds the hashes of the leaves, every other layer the parents of the one below,
+// parent i of children 2i, 2i+1, and the last one the root
+// with the parallel feature the leaves, and then every layer, are hashed in parallel
+pub struct MerkleTree<H: MerkleHasher = Sha256MerkleHasher> {
+    layers: Vec<Vec<H::Digest>>,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    pub fn new<L: AsRef<H::Leaf> + Sync>(leaves: &[L]) -> Self {
+        assert!(leaves.len().is_power_of_two(), "the number of leaves must be a power of two");
+        #[cfg(feature = "parallel")]
+        let iter = leaves.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let iter = leaves.iter();
+        Self::from_leaf_hashes(iter.map(|leaf| H::hash_leaf(leaf.as_ref())).collect())
+    }
+
+    // for leaves hashed some other way, see ColumnCommitment
+    fn from_leaf_hashes(leaf_hashes: Vec<H::Digest>) -> Self {
+        assert!(leaf_hashes.len().is_power_of_two(), "the number of leaves must be a power of two");
+        let mut layers = vec![leaf_hashes];
+        while layers.last().unwrap().len() > 1 {
+            let layer = layers.last().unwrap();
+            #[cfg(feature = "parallel")]
+            let pairs = layer.par_chunks_exact(2);
+            #[cfg(not(feature = "parallel"))]
+            let pairs = layer.chunks_exact(2);
+            let parents = pairs.map(|pair| H::hash_node(&pair[0], &pair[1])).collect();
+            layers.push(parents);
+        }
+        MerkleTree { layers }
+    }
+
+    pub fn root(&self) -> H::Digest {
+        self.layers.last().unwrap()[0].clone()
+    }
+
+    pub fn n_leaves(&self) -> usize {
+        self.layers[0].len()
+    }
+
+    // length of every path
+    pub fn height(&self) -> usize {
+        self.layers.len() - 1
+    }
+
+    // siblings of the nodes from the leaf at index up to the root, bottom up
+    pub fn open(&self, index: usize) -> Vec<H::Digest> {
+        assert!(index < self.n_leaves(), "leaf {} of a tree of {}", index, self.n_leaves());
+        self.layers[..self.height()]
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| layer[(index >> i) ^ 1].clone())
+            .collect()
+    }
+
+    // the path must be as long as the tree is high, which pins down the index: an index
+    // past 2^path.len() is rejected rather than read modulo the number of leaves
+    pub fn verify(root: &H::Digest, index: usize, leaf: &H::Leaf, path: &[H::Digest]) -> bool {
+        Self::verify_leaf_hash(root, index, H::hash_leaf(leaf), path)
+    }
+
+    fn verify_leaf_hash(root: &H::Digest, index: usize, leaf_hash: H::Digest, path: &[H::Digest]) -> bool {
+        // no tree is usize::BITS high, and index can't be shifted by that much
+        if path.len() >= usize::BITS as usize || index >> path.len() != 0 {
+            return false;
+        }
+        let mut node = leaf_hash;
+        for (i, sibling) in path.iter().enumerate() {
+            node = if (index >> i) & 1 == 1 {
+                H::hash_node(sibling, &node)
+            } else {
+                H::hash_node(&node, sibling)
+            };
+        }
+        node == *root
+    }
+
+    // one decommitment for all of the (strictly increasing) indices: the siblings of their
+    // paths, layer by layer and left to right, leaving out every node that is on another path
+    // or that the verifier computes from the layer below
+    pub fn open_many(&self, indices: &[usize]) -> MultiProof<H::Digest> {
+        assert!(indices.windows(2).all(|w| w[0] < w[1]), "indices must be strictly increasing");
+        assert!(indices.last().is_none_or(|i| *i < self.n_leaves()), "index past the last leaf");
+        let mut siblings = Vec::new();
+        let mut known = indices.to_vec();
+        for layer in &self.layers[..self.height()] {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut i = 0;
+            while i < known.len() {
+                let index = known[i];
+                if known.get(i + 1) == Some(&(index ^ 1)) {
+                    i += 2;
+                } else {
+                    siblings.push(layer[index ^ 1].clone());
+                    i += 1;
+                }
+                parents.push(index >> 1);
+            }
+            known = parents;
+        }
+        MultiProof { siblings }
+    }
+
+    // height is the verifier's own: unlike a path, the siblings don't tell how high the tree
+    // is. The indices must be strictly increasing and in the tree, and every sibling be used
+    pub fn verify_many<L: AsRef<H::Leaf>>(
+        root: &H::Digest,
+        height: usize,
+        indices: &[usize],
+        leaves: &[L],
+        proof: &MultiProof<H::Digest>,
+    ) -> bool {
+        if indices.len() != leaves.len() {
+            return false;
+        }
+        let leaf_hashes = leaves.iter().map(|leaf| H::hash_leaf(leaf.as_ref())).collect();
+        Self::verify_many_leaf_hashes(root, height, indices, leaf_hashes, proof)
+    }
+
+    fn verify_many_leaf_hashes(
+        root: &H::Digest,
+        height: usize,
+        indices: &[usize],
+        leaf_hashes: Vec<H::Digest>,
+        proof: &MultiProof<H::Digest>,
+    ) -> bool {
+        if indices.is_empty()
+            || indices.len() != leaf_hashes.len()
+            || !indices.windows(2).all(|w| w[0] < w[1])
+            || indices.last().unwrap().checked_shr(height as u32).unwrap_or(0) != 0
+        {
+            return false;
+        }
+        let mut siblings = proof.siblings.iter();
+        let mut nodes: Vec<(usize, H::Digest)> = indices.iter().copied().zip(leaf_hashes).collect();
+        for _ in 0..height {
+            let mut parents = Vec::with_capacity(nodes.len());
+            let mut i = 0;
+            while i < nodes.len() {
+                let index = nodes[i].0;
+                let parent = if nodes.get(i + 1).is_some_and(|(next, _)| *next == index ^ 1) {
+                    i += 2;
+                    H::hash_node(&nodes[i - 2].1, &nodes[i - 1].1)
+                } else {
+                    let Some(sibling) = siblings.next() else {
+                        return false;
+                    };
+                    i += 1;
+                    if index & 1 == 1 {
+                        H::hash_node(sibling, &nodes[i - 1].1)
+                    } else {
+                        H::hash_node(&nodes[i - 1].1, sibling)
+                    }
+                };
+                parents.push((index >> 1, parent));
+            }
+            nodes = parents;
+        }
+        siblings.next().is_none() && nodes[0].1 == *root
+    }
+}
+
+// the siblings open_many sends for a set of leaves, each one once
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiProof<D> {
+    pub siblings: Vec<D>,
+}
+
+// hashers that take a row of M31 values as a leaf
+pub trait M31MerkleHasher: MerkleHasher {
+    fn hash_m31s(values: &[FieldElement<M31>]) -> Self::Digest;
+}
+
+// the canonical little endian bytes of the values, as HashChannel mixes them
+impl<H: ChannelHash> M31MerkleHasher for HashMerkleHasher<H> {
+    fn hash_m31s(values: &[FieldElement<M31>]) -> [u8; 32] {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.representative().to_le_bytes()).collect();
+        Self::hash_leaf(&bytes)
+    }
+}
+
+impl M31MerkleHasher for PoseidonMerkleHasher {
+    fn hash_m31s(values: &[FieldElement<M31>]) -> [FieldElement<M31>; 8] {
+        Self::hash_leaf(values)
+    }
+}
+
+// the M31 coordinates of the values, in order, as one leaf
+fn hash_row<'a, F: ChannelField + 'static, H: M31MerkleHasher>(
+    row: impl Iterator<Item = &'a FieldElement<F>>,
+) -> H::Digest {
+    let coordinates: Vec<FieldElement<M31>> = row.flat_map(F::to_m31s).collect();
+    H::hash_m31s(&coordinates)
+}
+
+// one root for equally long columns (the columns of a trace, or of its LDE): leaf i is row i,
+// the M31 coordinates of the i-th value of every column, in column order. Opening a row
+// gives all of its values
+// with the parallel feature the rows are hashed in parallel
+pub struct ColumnCommitment<F: ChannelField, H: M31MerkleHasher = Sha256MerkleHasher> {
+    columns: Vec<Vec<FieldElement<F>>>,
+    tree: MerkleTree<H>,
+}
+
+impl<F: ChannelField + 'static, H: M31MerkleHasher> ColumnCommitment<F, H>
+where
+    FieldElement<F>: Send + Sync,
+{
+    pub fn new(columns: Vec<Vec<FieldElement<F>>>) -> Self {
+        assert!(!columns.is_empty(), "no columns to commit to");
+        let n_rows = columns[0].len();
+        assert!(columns.iter().all(|c| c.len() == n_rows), "columns of different lengths");
+        assert!(n_rows.is_power_of_two(), "the number of rows must be a power of two");
+        #[cfg(feature = "parallel")]
+        let rows = (0..n_rows).into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let rows = 0..n_rows;
+        let leaf_hashes = rows.map(|i| hash_row::<F, H>(columns.iter().map(|c| &c[i]))).collect();
+        ColumnCommitment { tree: MerkleTree::from_leaf_hashes(leaf_hashes), columns }
+    }
+
+    pub fn root(&self) -> H::Digest {
+        self.tree.root()
+    }
+
+    pub fn columns(&self) -> &[Vec<FieldElement<F>>] {
+        &self.columns
+    }
+
+    pub fn n_rows(&self) -> usize {
+        self.tree.n_leaves()
+    }
+
+    // length of every path
+    pub fn height(&self) -> usize {
+        self.tree.height()
+    }
+
+    pub fn row(&self, index: usize) -> Vec<FieldElement<F>> {
+        self.columns.iter().map(|c| c[index].clone()).collect()
+    }
+
+    // the row's values and its path
+    pub fn open_row(&self, index: usize) -> (Vec<FieldElement<F>>, Vec<H::Digest>) {
+        (self.row(index), self.tree.open(index))
+    }
+
+    pub fn verify_row(root: &H::Digest, index: usize, row: &[FieldElement<F>], path: &[H::Digest]) -> bool {
+        MerkleTree::<H>::verify_leaf_hash(root, index, hash_row::<F, H>(row.iter()), path)
+    }
+
+    // the rows at the (strictly increasing) indices and one decommitment for all of them
+    pub fn open_rows(&self, indices: &[usize]) -> (Vec<Vec<FieldElement<F>>>, MultiProof<H::Digest>) {
+        (indices.iter().map(|i| self.row(*i)).collect(), self.tree.open_many(indices))
+    }
+
+    pub fn verify_rows(
+        root: &H::Digest,
+        height: usize,
+        indices: &[usize],
+        rows: &[Vec<FieldElement<F>>],
+        proof: &MultiProof<H::Digest>,
+    ) -> bool {
+        let leaf_hashes = rows.iter().map(|row| hash_row::<F, H>(row.iter())).collect();
+        MerkleTree::<H>::verify_many_leaf_hashes(root, height, indices, leaf_hashes, proof)
+    }
 }
-pub fn get_branch(tree: Vec<Vec<u8>>, pos: usize) -> Vec<Vec<u8>>{
-    let offset_pos = (pos + tree.len())/2;
-    let branch_length = (tree.len() as u64).next_power_of_two().count_ones() as usize - 2;
-    (0..branch_length).map(|i| tree[(offset_pos >> i)^1].clone()).collect()
+
+// one root for columns of any power of two lengths (the components of a trace, of different
+// sizes): the leaves are the rows of the longest columns, and a column of length 2^k is
+// injected into the layer of 2^k nodes, whose node i is
+// hash_node(hash_node(left, right), row i of the columns of length 2^k), hashed as a leaf.
+// With columns of one length this is ColumnCommitment. Opening index i gives the value
+// of every column on its path, i.e. value i >> (height - k) of a column of length 2^k
+// with the parallel feature every layer is hashed in parallel
+pub struct MixedColumnCommitment<F: ChannelField, H: M31MerkleHasher = Sha256MerkleHasher> {
+    columns: Vec<Vec<FieldElement<F>>>,
+    tree: MerkleTree<H>,
 }
 
-pub fn verify_branch(root: Vec<u8>, mut pos: i32, val: Vec<u8>, branch: Vec<Vec<u8>>) -> bool {
-    let mut x = hash(val);
-    for b in branch{
-        if pos != 0{
-            let result: Vec<u8> = b.iter().zip(x.iter()).map(|(first, second)| first + second).collect();
-            x = hash(result);
-        } else {
-            let result: Vec<u8> = x.iter().zip(b.iter()).map(|(f, s)| f + s).collect();
-            x = hash(result);
+impl<F: ChannelField + 'static, H: M31MerkleHasher> MixedColumnCommitment<F, H>
+where
+    FieldElement<F>: Send + Sync,
+{
+    pub fn new(columns: Vec<Vec<FieldElement<F>>>) -> Self {
+        assert!(!columns.is_empty(), "no columns to commit to");
+        assert!(columns.iter().all(|c| c.len().is_power_of_two()), "column lengths must be powers of two");
+        let height = columns.iter().map(|c| c.len().ilog2()).max().unwrap();
+
+        // the columns of every layer, from the leaves up
+        let layer_columns: Vec<Vec<&Vec<FieldElement<F>>>> = (0..=height)
+            .rev()
+            .map(|log_size| columns.iter().filter(|c| c.len() == 1 << log_size).collect())
+            .collect();
+        let row =
+            |columns: &[&Vec<FieldElement<F>>], i: usize| hash_row::<F, H>(columns.iter().map(|c| &c[i]));
+
+        #[cfg(feature = "parallel")]
+        let rows = (0..1usize << height).into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let rows = 0..1usize << height;
+        let mut layers = vec![rows.map(|i| row(&layer_columns[0], i)).collect::<Vec<_>>()];
+        for injected in &layer_columns[1..] {
+            let children = layers.last().unwrap();
+            #[cfg(feature = "parallel")]
+            let nodes = (0..children.len() / 2).into_par_iter();
+            #[cfg(not(feature = "parallel"))]
+            let nodes = 0..children.len() / 2;
+            let parents = nodes
+                .map(|i| {
+                    let node = H::hash_node(&children[2 * i], &children[2 * i + 1]);
+                    if injected.is_empty() {
+                        node
+                    } else {
+                        H::hash_node(&node, &row(injected, i))
+                    }
+                })
+                .collect();
+            layers.push(parents);
+        }
+        MixedColumnCommitment { tree: MerkleTree { layers }, columns }
+    }
+
+    pub fn root(&self) -> H::Digest {
+        self.tree.root()
+    }
+
+    pub fn columns(&self) -> &[Vec<FieldElement<F>>] {
+        &self.columns
+    }
+
+    // what the verifier needs to know of the columns
+    pub fn column_log_sizes(&self) -> Vec<u32> {
+        self.columns.iter().map(|c| c.len().ilog2()).collect()
+    }
+
+    // log size of the longest columns, and length of every path
+    pub fn height(&self) -> usize {
+        self.tree.height()
+    }
+
+    // the value of every column on the path of leaf index, in column order, and the path
+    pub fn open(&self, index: usize) -> (Vec<FieldElement<F>>, Vec<H::Digest>) {
+        let height = self.height();
+        let values = self
+            .columns
+            .iter()
+            .map(|c| c[index >> (height - c.len().ilog2() as usize)].clone())
+            .collect();
+        (values, self.tree.open(index))
+    }
+
+    // column_log_sizes are the verifier's own, and give the height: the path must be as long
+    pub fn verify(
+        root: &H::Digest,
+        column_log_sizes: &[u32],
+        index: usize,
+        values: &[FieldElement<F>],
+        path: &[H::Digest],
+    ) -> bool {
+        if values.len() != column_log_sizes.len()
+            || column_log_sizes.iter().max().map(|h| *h as usize) != Some(path.len())
+            || path.len() >= usize::BITS as usize
+            || index >> path.len() != 0
+        {
+            return false;
         }
-        pos = pos/2;
+        let layer_values = |log_size: usize| {
+            values.iter().zip(column_log_sizes).filter(move |(_, s)| **s as usize == log_size).map(|(v, _)| v)
+        };
+        let mut node = hash_row::<F, H>(layer_values(path.len()));
+        for (i, sibling) in path.iter().enumerate() {
+            node = if (index >> i) & 1 == 1 {
+                H::hash_node(sibling, &node)
+            } else {
+                H::hash_node(&node, sibling)
+            };
+            let log_size = path.len() - i - 1;
+            if column_log_sizes.iter().any(|s| *s as usize == log_size) {
+                node = H::hash_node(&node, &hash_row::<F, H>(layer_values(log_size)));
+            }
+        }
+        node == *root
     }
-    return x == root
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qm31::QM31;
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // vectors from python's hashlib, with the RFC 6962 prefixes
+    #[test]
+    fn matches_the_reference_vectors() {
+        let tree = MerkleTree::<Sha256MerkleHasher>::new(&[b""]);
+        assert_eq!(
+            to_hex(&tree.root()),
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d"
+        );
+        assert_eq!(tree.height(), 0);
+        assert!(tree.open(0).is_empty());
+
+        let leaves = [b"a", b"b", b"c", b"d"];
+        let tree = MerkleTree::<Sha256MerkleHasher>::new(&leaves);
+        assert_eq!(
+            to_hex(&tree.layers[0][0]),
+            "022a6979e6dab7aa5ae4c3e5e45f7e977112a7e63593820dbec1ec738a24f93c"
+        );
+        assert_eq!(
+            to_hex(&tree.root()),
+            "33376a3bd63e9993708a84ddfe6c28ae58b83505dd1fed711bd924ec5a6239f0"
+        );
+        assert_eq!(
+            to_hex(&MerkleTree::<Blake2sMerkleHasher>::new(&leaves).root()),
+            "47c32c2f701fb6272be2a4eb2fcfe5d46cdbd551ff3e0573f9e9fe1a6f4c78e2"
+        );
+        // no blake3 in hashlib, the hash itself against the vector of its spec
+        assert_eq!(
+            to_hex(&Blake3Hash::hash(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+    }
+
+    fn byte_leaves(n: u32) -> Vec<Vec<u8>> {
+        (0..n).map(|i| i.to_le_bytes().to_vec()).collect()
+    }
+
+    // leaves of 0 to 9 elements, across the chunks of 8 the leaf hash absorbs
+    fn m31_leaves(n: u32) -> Vec<Vec<FieldElement<M31>>> {
+        (0..n).map(|i| (0..i % 10).map(|j| FieldElement::from((i * 10 + j) as u64)).collect()).collect()
+    }
+
+    fn check_layers<H: MerkleHasher, L: AsRef<H::Leaf> + Sync>(leaves_of: fn(u32) -> Vec<L>) {
+        for log_size in 0..=10 {
+            let leaves = leaves_of(1 << log_size);
+            let tree = MerkleTree::<H>::new(&leaves);
+            assert_eq!(tree.height(), log_size);
+            for (leaf, hash) in leaves.iter().zip(&tree.layers[0]) {
+                assert_eq!(*hash, H::hash_leaf(leaf.as_ref()));
+            }
+            for (children, parents) in tree.layers.iter().zip(&tree.layers[1..]) {
+                for (i, parent) in parents.iter().enumerate() {
+                    assert_eq!(*parent, H::hash_node(&children[2 * i], &children[2 * i + 1]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layers_are_hashed_like_the_sequential_definition() {
+        check_layers::<Sha256MerkleHasher, _>(byte_leaves);
+        check_layers::<Blake2sMerkleHasher, _>(byte_leaves);
+        check_layers::<Blake3MerkleHasher, _>(byte_leaves);
+        check_layers::<PoseidonMerkleHasher, _>(m31_leaves);
+    }
+
+    fn check_paths<H: MerkleHasher, L: AsRef<H::Leaf> + Sync>(leaves_of: fn(u32) -> Vec<L>) {
+        let leaves = leaves_of(16);
+        let tree = MerkleTree::<H>::new(&leaves);
+        let root = tree.root();
+        for (index, leaf) in leaves.iter().enumerate() {
+            let (leaf, other) = (leaf.as_ref(), leaves[(index + 1) % 16].as_ref());
+            let path = tree.open(index);
+            assert_eq!(path.len(), 4);
+            assert!(MerkleTree::<H>::verify(&root, index, leaf, &path));
+            assert!(!MerkleTree::<H>::verify(&root, index ^ 1, leaf, &path));
+            assert!(!MerkleTree::<H>::verify(&root, index + 16, leaf, &path));
+            assert!(!MerkleTree::<H>::verify(&root, index, other, &path));
+            assert!(!MerkleTree::<H>::verify(&root, index / 2, leaf, &path[1..]));
+        }
+        let oversized = vec![root.clone(); usize::BITS as usize + 1];
+        assert!(!MerkleTree::<H>::verify(&root, 0, leaves[0].as_ref(), &oversized));
+    }
+
+    #[test]
+    fn paths_verify_against_the_root() {
+        check_paths::<Sha256MerkleHasher, _>(byte_leaves);
+        check_paths::<Blake2sMerkleHasher, _>(byte_leaves);
+        check_paths::<Blake3MerkleHasher, _>(byte_leaves);
+        check_paths::<PoseidonMerkleHasher, _>(m31_leaves);
+
+        // an internal node does not open as a leaf: the two children it hashes are not one
+        // leaf's data under the leaf prefix
+        let tree = MerkleTree::<Sha256MerkleHasher>::new(&byte_leaves(16));
+        let children = [tree.layers[0][0], tree.layers[0][1]].concat();
+        assert!(!MerkleTree::<Sha256MerkleHasher>::verify(&tree.root(), 0, &children, &tree.open(0)[1..]));
+        let tree = MerkleTree::<PoseidonMerkleHasher>::new(&m31_leaves(16));
+        let children = [tree.layers[0][0], tree.layers[0][1]].concat();
+        assert!(!MerkleTree::<PoseidonMerkleHasher>::verify(&tree.root(), 0, &children, &tree.open(0)[1..]));
+    }
+
+    fn check_multiproofs<H: MerkleHasher, L: AsRef<H::Leaf> + Sync>(leaves_of: fn(u32) -> Vec<L>) {
+        let leaves = leaves_of(64);
+        let tree = MerkleTree::<H>::new(&leaves);
+        let root = tree.root();
+        let verify = |indices: &[usize], proof: &MultiProof<H::Digest>| {
+            let opened: Vec<&L> = indices.iter().map(|i| &leaves[*i]).collect();
+            MerkleTree::<H>::verify_many(&root, 6, indices, &opened, proof)
+        };
+
+        for indices in [vec![0], vec![5, 6], vec![4, 5], vec![0, 1, 2, 3, 40, 63], (0..64).collect()] {
+            let proof = tree.open_many(&indices);
+            assert!(verify(&indices, &proof));
+            // shared siblings are sent once, computable ones not at all
+            assert!(proof.siblings.len() <= indices.len() * 6);
+        }
+        assert_eq!(tree.open_many(&[9]).siblings, tree.open(9));
+        assert_eq!(tree.open_many(&[4, 5]).siblings.len(), 5);
+        assert!(tree.open_many(&(0..64).collect::<Vec<_>>()).siblings.is_empty());
+
+        let indices = [3, 17, 18, 50];
+        let proof = tree.open_many(&indices);
+        let opened: Vec<&L> = indices.iter().map(|i| &leaves[*i]).collect();
+        assert!(MerkleTree::<H>::verify_many(&root, 6, &indices, &opened, &proof));
+        assert!(!MerkleTree::<H>::verify_many(&root, 5, &indices, &opened, &proof));
+        assert!(!MerkleTree::<H>::verify_many(&root, 7, &indices, &opened, &proof));
+        assert!(!verify(&[3, 17, 19, 50], &proof));
+        assert!(!MerkleTree::<H>::verify_many(&root, 6, &[3, 17, 18, 51], &opened, &proof));
+        assert!(!MerkleTree::<H>::verify_many(&root, 6, &[3, 18, 17, 50], &opened, &proof));
+        assert!(!MerkleTree::<H>::verify_many(&root, 6, &[3, 17, 18, 64], &opened, &proof));
+        assert!(!MerkleTree::<H>::verify_many(&root, 6, &indices, &opened[1..], &proof));
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(!MerkleTree::<H>::verify_many(&root, 6, &indices, &opened, &short));
+        let mut long = proof.clone();
+        long.siblings.push(proof.siblings[0].clone());
+        assert!(!MerkleTree::<H>::verify_many(&root, 6, &indices, &opened, &long));
+        let mut swapped = proof.clone();
+        swapped.siblings.swap(0, 1);
+        assert!(!MerkleTree::<H>::verify_many(&root, 6, &indices, &opened, &swapped));
+    }
+
+    #[test]
+    fn multiproofs_verify_and_share_siblings() {
+        check_multiproofs::<Sha256MerkleHasher, _>(byte_leaves);
+        check_multiproofs::<Blake3MerkleHasher, _>(byte_leaves);
+        check_multiproofs::<PoseidonMerkleHasher, _>(m31_leaves);
+    }
+
+    #[test]
+    fn poseidon_leaves_are_padded_unambiguously() {
+        let one = FieldElement::<M31>::one();
+        let zero = FieldElement::<M31>::zero();
+        let hashes: Vec<_> = [vec![], vec![zero], vec![one], vec![one, zero], vec![one; 8], vec![one; 9]]
+            .iter()
+            .map(|leaf| PoseidonMerkleHasher::hash_leaf(leaf))
+            .collect();
+        for (i, a) in hashes.iter().enumerate() {
+            assert!(hashes[i + 1..].iter().all(|b| a != b));
+        }
+        // one chunk: the leaf and its padding, absorbed into the initial digest
+        let mut chunk = [zero; 8];
+        chunk[0] = one;
+        assert_eq!(hashes[0], poseidon_hash(&PoseidonMerkleHasher::leaf_iv(), &chunk));
+    }
+
+    fn m31_columns(n_columns: u64, n_rows: u64) -> Vec<Vec<FieldElement<M31>>> {
+        (0..n_columns)
+            .map(|c| (0..n_rows).map(|r| FieldElement::from(c * 1000 + r * r + 1)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn column_rows_are_the_leaves() {
+        let columns = m31_columns(3, 16);
+        let rows: Vec<Vec<FieldElement<M31>>> = (0..16).map(|r| columns.iter().map(|c| c[r]).collect()).collect();
+
+        let commitment = ColumnCommitment::<M31, Sha256MerkleHasher>::new(columns.clone());
+        let row_bytes: Vec<Vec<u8>> = rows
+            .iter()
+            .map(|row| row.iter().flat_map(|v| v.representative().to_le_bytes()).collect())
+            .collect();
+        assert_eq!(commitment.root(), MerkleTree::<Sha256MerkleHasher>::new(&row_bytes).root());
+
+        let commitment = ColumnCommitment::<M31, PoseidonMerkleHasher>::new(columns);
+        assert_eq!(commitment.root(), MerkleTree::<PoseidonMerkleHasher>::new(&rows).root());
+    }
+
+    fn check_column_openings<F: ChannelField + 'static, H: M31MerkleHasher>(columns: Vec<Vec<FieldElement<F>>>)
+    where
+        FieldElement<F>: Send + Sync,
+    {
+        let commitment = ColumnCommitment::<F, H>::new(columns.clone());
+        let root = commitment.root();
+        let n_rows = commitment.n_rows();
+        for index in 0..n_rows {
+            let (row, path) = commitment.open_row(index);
+            assert_eq!(row, columns.iter().map(|c| c[index].clone()).collect::<Vec<_>>());
+            assert!(ColumnCommitment::<F, H>::verify_row(&root, index, &row, &path));
+            assert!(!ColumnCommitment::<F, H>::verify_row(&root, index ^ 1, &row, &path));
+            // every value of the row is bound, and so is its column
+            let mut changed = row.clone();
+            changed[0] += FieldElement::<F>::one();
+            assert!(!ColumnCommitment::<F, H>::verify_row(&root, index, &changed, &path));
+            assert!(!ColumnCommitment::<F, H>::verify_row(&root, index, &row[1..], &path));
+            let mut swapped = row.clone();
+            swapped.swap(0, row.len() - 1);
+            assert!(swapped == row || !ColumnCommitment::<F, H>::verify_row(&root, index, &swapped, &path));
+        }
+
+        let indices = [1, 2, n_rows - 1];
+        let (rows, proof) = commitment.open_rows(&indices);
+        let height = commitment.height();
+        assert!(ColumnCommitment::<F, H>::verify_rows(&root, height, &indices, &rows, &proof));
+        let mut changed = rows.clone();
+        changed[2][0] += FieldElement::<F>::one();
+        assert!(!ColumnCommitment::<F, H>::verify_rows(&root, height, &indices, &changed, &proof));
+    }
+
+    #[test]
+    fn rows_open_with_all_their_values() {
+        check_column_openings::<M31, Sha256MerkleHasher>(m31_columns(3, 16));
+        check_column_openings::<M31, Blake2sMerkleHasher>(m31_columns(1, 8));
+        check_column_openings::<M31, PoseidonMerkleHasher>(m31_columns(5, 16));
+
+        // a QM31 column is its 4 coordinates
+        let coordinates = m31_columns(8, 8);
+        let qm31_columns: Vec<Vec<FieldElement<QM31>>> = coordinates
+            .chunks(4)
+            .map(|c| (0..8).map(|r| QM31::from_m31s(&[c[0][r], c[1][r], c[2][r], c[3][r]])).collect())
+            .collect();
+        let commitment = ColumnCommitment::<QM31, PoseidonMerkleHasher>::new(qm31_columns.clone());
+        assert_eq!(commitment.root(), ColumnCommitment::<M31, PoseidonMerkleHasher>::new(coordinates).root());
+        check_column_openings::<QM31, PoseidonMerkleHasher>(qm31_columns);
+    }
+
+    #[test]
+    #[should_panic(expected = "columns of different lengths")]
+    fn columns_must_be_equally_long() {
+        let mut columns = m31_columns(2, 8);
+        columns[1].pop();
+        ColumnCommitment::<M31, Sha256MerkleHasher>::new(columns);
+    }
+
+    // columns of lengths 2^log_sizes[i]
+    fn mixed_columns(log_sizes: &[u32]) -> Vec<Vec<FieldElement<M31>>> {
+        log_sizes
+            .iter()
+            .enumerate()
+            .map(|(c, log_size)| {
+                (0..1u64 << log_size).map(|r| FieldElement::from(c as u64 * 1000 + r * r + 1)).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mixed_columns_are_injected_at_their_height() {
+        // one column of length 4 and one of length 2
+        let columns = mixed_columns(&[2, 1]);
+        let commitment = MixedColumnCommitment::<M31, Sha256MerkleHasher>::new(columns.clone());
+        let leaf = |v: &FieldElement<M31>| Sha256MerkleHasher::hash_m31s(&[*v]);
+        let node = |l, r| Sha256MerkleHasher::hash_node(&l, &r);
+        let n0 = node(node(leaf(&columns[0][0]), leaf(&columns[0][1])), leaf(&columns[1][0]));
+        let n1 = node(node(leaf(&columns[0][2]), leaf(&columns[0][3])), leaf(&columns[1][1]));
+        assert_eq!(commitment.root(), node(n0, n1));
+
+        // with columns of one length nothing is injected
+        let columns = m31_columns(3, 16);
+        assert_eq!(
+            MixedColumnCommitment::<M31, PoseidonMerkleHasher>::new(columns.clone()).root(),
+            ColumnCommitment::<M31, PoseidonMerkleHasher>::new(columns).root()
+        );
+    }
+
+    #[test]
+    fn mixed_openings_return_every_column_on_the_path() {
+        let log_sizes = [3, 4, 1, 4, 0, 3];
+        let columns = mixed_columns(&log_sizes);
+        type Mixed = MixedColumnCommitment<M31, Blake3MerkleHasher>;
+        let commitment = Mixed::new(columns.clone());
+        let root = commitment.root();
+        assert_eq!(commitment.height(), 4);
+        assert_eq!(commitment.column_log_sizes(), log_sizes);
+
+        for index in 0..16 {
+            let (values, path) = commitment.open(index);
+            let expected: Vec<_> = columns.iter().zip(log_sizes).map(|(c, s)| c[index >> (4 - s)]).collect();
+            assert_eq!(values, expected);
+            assert_eq!(path.len(), 4);
+            assert!(Mixed::verify(&root, &log_sizes, index, &values, &path));
+
+            // a changed value in any column, short ones included, is caught
+            for c in 0..values.len() {
+                let mut changed = values.clone();
+                changed[c] += FieldElement::<M31>::one();
+                assert!(!Mixed::verify(&root, &log_sizes, index, &changed, &path));
+            }
+            // and so are wrong shapes and indices
+            let verify = |log_sizes: &[u32], index, values: &[_], path: &[_]| {
+                Mixed::verify(&root, log_sizes, index, values, path)
+            };
+            assert!(!verify(&[3, 4, 2, 4, 0, 3], index, &values, &path));
+            assert!(!verify(&log_sizes[1..], index, &values[1..], &path));
+            assert!(!verify(&log_sizes, index ^ 1, &values, &path));
+            assert!(!verify(&log_sizes, index + 16, &values, &path));
+            assert!(!verify(&log_sizes, index, &values, &path[1..]));
+        }
+        let oversized = vec![root; usize::BITS as usize + 1];
+        assert!(!Mixed::verify(&root, &[usize::BITS + 1], 0, &[FieldElement::zero()], &oversized));
+    }
+
+    #[test]
+    #[should_panic(expected = "powers of two")]
+    fn mixed_column_lengths_must_be_powers_of_two() {
+        let mut columns = mixed_columns(&[3, 2]);
+        columns[1].pop();
+        MixedColumnCommitment::<M31, Sha256MerkleHasher>::new(columns);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::circle::MODULUS;
+use crate::utils::HALF;
+use lambdaworks_math::field::element::FieldElement;
+use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// packed M31 arithmetic for the FFT butterflies and the FRI fold, which only need M31 twiddles
+// and work coordinate-wise on extension values
+
+// 16 lanes fill an AVX-512 register, or two AVX2 ones
+pub const LANES: usize = 16;
+
+const P: u32 = MODULUS;
+
+// LANES elements of M31, every lane in [0, P] (P being another representation of 0, as in
+// lambdaworks). The operations are branchless loops over the lanes so that they vectorize:
+// x < 2^62 reduces to (x & P) + (x >> 31) since 2^31 = 1 mod P, and a value below 2P is
+// brought back below P+1 by a min with its value minus P (which wraps around when smaller)
+#[derive(Clone, Copy, Debug)]
+pub struct PackedM31([u32; LANES]);
+
+#[inline(always)]
+fn reduce(x: u32) -> u32 {
+    x.min(x.wrapping_sub(P))
+}
+
+impl PackedM31 {
+    #[inline(always)]
+    pub fn broadcast(x: FieldElement<M31>) -> Self {
+        PackedM31([x.to_raw(); LANES])
+    }
+
+    #[inline(always)]
+    pub fn from_fn(mut f: impl FnMut(usize) -> FieldElement<M31>) -> Self {
+        PackedM31(std::array::from_fn(|i| f(i).to_raw()))
+    }
+
+    // the first LANES values of vals
+    #[inline(always)]
+    pub fn from_slice(vals: &[FieldElement<M31>]) -> Self {
+        PackedM31::from_fn(|i| vals[i])
+    }
+
+    #[inline(always)]
+    pub fn write_to(self, out: &mut [FieldElement<M31>]) {
+        for (o, x) in out[..LANES].iter_mut().zip(self.0) {
+            *o = FieldElement::from_raw(x);
+        }
+    }
+
+    pub fn to_array(self) -> [FieldElement<M31>; LANES] {
+        self.0.map(FieldElement::from_raw)
+    }
+
+    #[inline(always)]
+    pub fn square(self) -> Self {
+        self * self
+    }
+
+    // self^(2^k)
+    #[inline(always)]
+    fn exp_power_of_2(self, k: u32) -> Self {
+        let mut res = self;
+        for _ in 0..k {
+            res = res.square();
+        }
+        res
+    }
+
+    // x^(P-2) in every lane, with the addition chain lambdaworks uses for a single element
+    // lanes holding 0 stay 0
+    #[inline(always)]
+    pub fn inverse(self) -> Self {
+        let p101 = self.exp_power_of_2(2) * self;
+        let p1111 = p101.square() * p101;
+        let p11111111 = p1111.exp_power_of_2(4) * p1111;
+        let p111111110000 = p11111111.exp_power_of_2(4);
+        let p111111111111 = p111111110000 * p1111;
+        let p1111111111111111 = p111111110000.exp_power_of_2(4) * p11111111;
+        let p1111111111111111111111111111 =
+            p1111111111111111.exp_power_of_2(12) * p111111111111;
+        p1111111111111111111111111111.exp_power_of_2(3) * p101
+    }
+}
+
+impl PartialEq for PackedM31 {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.iter().zip(other.0).all(|(a, b)| a % P == b % P)
+    }
+}
+
+impl Eq for PackedM31 {}
+
+impl Add for PackedM31 {
+    type Output = PackedM31;
+    #[inline(always)]
+    fn add(self, rhs: Self) -> Self::Output {
+        PackedM31(std::array::from_fn(|i| reduce(self.0[i] + rhs.0[i])))
+    }
+}
+
+// a - b, plus P when it wraps around
+impl Sub for PackedM31 {
+    type Output = PackedM31;
+    #[inline(always)]
+    fn sub(self, rhs: Self) -> Self::Output {
+        PackedM31(std::array::from_fn(|i| {
+            let diff = self.0[i].wrapping_sub(rhs.0[i]);
+            diff.min(diff.wrapping_add(P))
+        }))
+    }
+}
+
+impl Neg for PackedM31 {
+    type Output = PackedM31;
+    #[inline(always)]
+    fn neg(self) -> Self::Output {
+        PackedM31(self.0.map(|x| P - x))
+    }
+}
+
+impl Mul for PackedM31 {
+    type Output = PackedM31;
+    #[inline(always)]
+    fn mul(self, rhs: Self) -> Self::Output {
+        PackedM31(std::array::from_fn(|i| {
+            let prod = self.0[i] as u64 * rhs.0[i] as u64;
+            reduce((prod as u32 & P) + (prod >> 31) as u32)
+        }))
+    }
+}
+
+// defines pub fn $name, which runs $kernel compiled for AVX-512 or AVX2 when the cpu
+// supports them (checked at runtime), and as portable code otherwise. The kernels and the
+// PackedM31 operations are inline(always) so that they are compiled with the enabled features
+macro_rules! dispatch {
+    ($(#[$doc:meta])* pub fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)? => $kernel:ident) => {
+        $(#[$doc])*
+        pub fn $name($($arg: $ty),*) $(-> $ret)? {
+            #[cfg(target_arch = "x86_64")]
+            {
+                #[target_feature(enable = "avx512f")]
+                fn avx512($($arg: $ty),*) $(-> $ret)? {
+                    $kernel($($arg),*)
+                }
+                #[target_feature(enable = "avx2")]
+                fn avx2($($arg: $ty),*) $(-> $ret)? {
+                    $kernel($($arg),*)
+                }
+                if is_x86_feature_detected!("avx512f") {
+                    // SAFETY: the cpu supports avx512f
+                    return unsafe { avx512($($arg),*) };
+                }
+                if is_x86_feature_detected!("avx2") {
+                    // SAFETY: the cpu supports avx2
+                    return unsafe { avx2($($arg),*) };
+                }
+            }
+            $kernel($($arg),*)
+        }
+    };
+}
+
+// (a, b) -> (a + b, (a - b)*inv_t) for every a in left and the b at the same position in right
+#[inline(always)]
+fn ibutterflies_kernel(
+    left: &mut [FieldElement<M31>],
+    right: &mut [FieldElement<M31>],
+    inv_t: &FieldElement<M31>,
+) {
+    let t = PackedM31::broadcast(*inv_t);
+    let mut left_chunks = left.chunks_exact_mut(LANES);
+    let mut right_chunks = right.chunks_exact_mut(LANES);
+    for (l, r) in (&mut left_chunks).zip(&mut right_chunks) {
+        let (a, b) = (PackedM31::from_slice(l), PackedM31::from_slice(r));
+        (a + b).write_to(l);
+        ((a - b) * t).write_to(r);
+    }
+    let remainder = left_chunks.into_remainder().iter_mut();
+    for (a, b) in remainder.zip(right_chunks.into_remainder()) {
+        let diff = *a - *b;
+        *a += *b;
+        *b = inv_t * diff;
+    }
+}
+
+// (a, b) -> (a + t*b, a - t*b)
+#[inline(always)]
+fn butterflies_kernel(
+    left: &mut [FieldElement<M31>],
+    right: &mut [FieldElement<M31>],
+    t: &FieldElement<M31>,
+) {
+    let packed_t = PackedM31::broadcast(*t);
+    let mut left_chunks = left.chunks_exact_mut(LANES);
+    let mut right_chunks = right.chunks_exact_mut(LANES);
+    for (l, r) in (&mut left_chunks).zip(&mut right_chunks) {
+        let (a, b) = (PackedM31::from_slice(l), PackedM31::from_slice(r));
+        let tb = b * packed_t;
+        (a + tb).write_to(l);
+        (a - tb).write_to(r);
+    }
+    let remainder = left_chunks.into_remainder().iter_mut();
+    for (a, b) in remainder.zip(right_chunks.into_remainder()) {
+        let tb = t * *b;
+        *b = *a - tb;
+        *a += tb;
+    }
+}
+
+// (a, b) -> ((a + b)/2, (a - b)*inv_t/2) for the pairs a = values[2k], b = values[2k+1] and
+// inv_t = inv_ts[k], written to f0[k] and f1[k]
+#[inline(always)]
+fn fold_halves_kernel(
+    values: &[FieldElement<M31>],
+    inv_ts: &[FieldElement<M31>],
+    f0: &mut [FieldElement<M31>],
+    f1: &mut [FieldElement<M31>],
+) {
+    assert_eq!(values.len(), 2 * inv_ts.len());
+    let half = FieldElement::<M31>::from(HALF as u64);
+    let packed_half = PackedM31::broadcast(half);
+    let mut f0_chunks = f0.chunks_exact_mut(LANES);
+    let mut f1_chunks = f1.chunks_exact_mut(LANES);
+    let mut value_chunks = values.chunks_exact(2 * LANES);
+    let mut t_chunks = inv_ts.chunks_exact(LANES);
+    for (((out0, out1), vals), t) in (&mut f0_chunks)
+        .zip(&mut f1_chunks)
+        .zip(&mut value_chunks)
+        .zip(&mut t_chunks)
+    {
+        let a = PackedM31::from_fn(|i| vals[2 * i]);
+        let b = PackedM31::from_fn(|i| vals[2 * i + 1]);
+        ((a + b) * packed_half).write_to(out0);
+        ((a - b) * packed_half * PackedM31::from_slice(t)).write_to(out1);
+    }
+    let remainder = f0_chunks.into_remainder().iter_mut().zip(f1_chunks.into_remainder());
+    for ((out0, out1), (pair, t)) in
+        remainder.zip(value_chunks.remainder().chunks_exact(2).zip(t_chunks.remainder()))
+    {
+        *out0 = (pair[0] + pair[1]) * half;
+        *out1 = (pair[0] - pair[1]) * half * t;
+    }
+}
+
+dispatch!(
+    // inverse fft butterflies of a block, all with the same twiddle
+    pub fn ibutterflies(
+        left: &mut [FieldElement<M31>],
+        right: &mut [FieldElement<M31>],
+        inv_t: &FieldElement<M31>
+    ) => ibutterflies_kernel
+);
+
+dispatch!(
+    // fft butterflies of a block, all with the same twiddle
+    pub fn butterflies(
+        left: &mut [FieldElement<M31>],
+        right: &mut [FieldElement<M31>],
+        t: &FieldElement<M31>
+    ) => butterflies_kernel
+);
+
+dispatch!(
+    // the M31 part of a FRI fold, done on every coordinate of extension values
+    pub fn fold_halves(
+        values: &[FieldElement<M31>],
+        inv_ts: &[FieldElement<M31>],
+        f0: &mut [FieldElement<M31>],
+        f1: &mut [FieldElement<M31>]
+    ) => fold_halves_kernel
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::random_values;
+
+    fn random_packed(seed: u64) -> PackedM31 {
+        PackedM31::from_slice(&random_values(LANES, seed))
+    }
+
+    // applies op lane by lane with lambdaworks' scalar arithmetic
+    fn lanewise(
+        a: PackedM31,
+        b: PackedM31,
+        op: impl Fn(FieldElement<M31>, FieldElement<M31>) -> FieldElement<M31>,
+    ) -> PackedM31 {
+        let (a, b) = (a.to_array(), b.to_array());
+        PackedM31::from_fn(|i| op(a[i], b[i]))
+    }
+
+    #[test]
+    fn operations_match_the_scalar_field() {
+        // edge lanes: 0, 1, P-1 and P itself (0 in lambdaworks' representation)
+        let edges = PackedM31::from_fn(|i| FieldElement::from_raw([0, 1, P - 1, P][i % 4]));
+        for seed in 0..100 {
+            for (a, b) in [
+                (random_packed(seed), random_packed(seed + 1000)),
+                (edges, random_packed(seed)),
+                (random_packed(seed), edges),
+            ] {
+                assert_eq!(a + b, lanewise(a, b, |x, y| x + y));
+                assert_eq!(a - b, lanewise(a, b, |x, y| x - y));
+                assert_eq!(a * b, lanewise(a, b, |x, y| x * y));
+                assert_eq!(-a, lanewise(a, a, |x, _| -x));
+                assert_eq!(a.square(), lanewise(a, a, |x, _| x.square()));
+                for lane in (a + b).0.iter().chain(&(a - b).0).chain(&(a * b).0) {
+                    assert!(*lane <= P);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_matches_the_scalar_field() {
+        for seed in 0..20 {
+            let a = random_packed(seed);
+            let expected = lanewise(a, a, |x, _| x.inv().unwrap());
+            assert_eq!(a.inverse(), expected);
+            assert_eq!(a * a.inverse(), PackedM31::broadcast(FieldElement::one()));
+        }
+        let zero = PackedM31::broadcast(FieldElement::zero());
+        assert_eq!(zero.inverse(), zero);
+    }
+
+    #[test]
+    fn kernels_match_the_scalar_butterflies_and_fold() {
+        // not a multiple of LANES, so the scalar remainder is covered too
+        let n = 3 * LANES + 5;
+        let left: Vec<FieldElement<M31>> =
+            (0..n).map(|i| FieldElement::from(i as u64 * 7919 + 1)).collect();
+        let right: Vec<FieldElement<M31>> =
+            (0..n).map(|i| FieldElement::from(i as u64 * 104729 + 3)).collect();
+        let t = FieldElement::<M31>::from(123456789);
+
+        let (mut l, mut r) = (left.clone(), right.clone());
+        butterflies(&mut l, &mut r, &t);
+        for i in 0..n {
+            assert_eq!(l[i], left[i] + t * right[i]);
+            assert_eq!(r[i], left[i] - t * right[i]);
+        }
+        ibutterflies(&mut l, &mut r, &t.inv().unwrap());
+        for i in 0..n {
+            assert_eq!(l[i], left[i].double());
+            assert_eq!(r[i], right[i].double());
+        }
+
+        let values: Vec<FieldElement<M31>> = left.iter().chain(&right).cloned().collect();
+        let inv_ts = crate::utils::batch_inverse(&right).unwrap();
+        let (mut f0, mut f1) = (vec![FieldElement::zero(); n], vec![FieldElement::zero(); n]);
+        fold_halves(&values, &inv_ts, &mut f0, &mut f1);
+        let two = FieldElement::<M31>::from(2);
+        for k in 0..n {
+            let (a, b) = (values[2 * k], values[2 * k + 1]);
+            assert_eq!(f0[k], (a + b) / two);
+            assert_eq!(f1[k], (a - b) / (two * right[k]));
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use crate::circle::{CircleImpl, CirclePoint};
+use crate::domain::{CanonicCoset, CircleDomain};
+use crate::fast_fft::{bary_eval, fft, inv_fft};
+use crate::precomputes::TwiddleTree;
+use crate::utils::bit_reverse;
+use lambdaworks_math::field::element::FieldElement;
+use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use lambdaworks_math::field::traits::{IsField, IsSubFieldOf};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// blowup factors 2, 4, 8 and 16
+pub const MAX_LOG_BLOWUP: u32 = 4;
+
+// a circle polynomial in coefficient form, over the basis 1, y, x, xy, 2x^2-1, ... (see fft.rs)
+// the number of coefficients is a power of 2
+#[derive(Clone, Debug, PartialEq)]
+pub struct CirclePoly<F: IsField = M31> {
+    coeffs: Vec<FieldElement<F>>,
+}
+
+impl<F: IsField + 'static> CirclePoly<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    pub fn new(coeffs: Vec<FieldElement<F>>) -> Self {
+        assert!(coeffs.len().is_power_of_two());
+        CirclePoly { coeffs }
+    }
+
+    pub fn coeffs(&self) -> &[FieldElement<F>] {
+        &self.coeffs
+    }
+
+    pub fn log_size(&self) -> u32 {
+        self.coeffs.len().ilog2()
+    }
+
+    // total degree in x and y. The k-th basis element has degree ceil(k/2): bit 0 is y and
+    // bit j > 0 is the (j-1)-th doubling of x, of degree 2^(j-1). The zero polynomial has degree 0
+    pub fn degree(&self) -> usize {
+        match self.coeffs.iter().rposition(|c| *c != FieldElement::zero()) {
+            Some(k) => k.div_ceil(2),
+            None => 0,
+        }
+    }
+
+    // the polynomial with all the coefficients from 2^log_size on dropped
+    pub fn truncate(&self, log_size: u32) -> Self {
+        assert!(log_size <= self.log_size());
+        CirclePoly::new(self.coeffs[..1 << log_size].to_vec())
+    }
+
+    // the same polynomial with 2^log_size coefficients
+    pub fn extend(&self, log_size: u32) -> Self {
+        assert!(log_size >= self.log_size());
+        let mut coeffs = self.coeffs.clone();
+        coeffs.resize(1 << log_size, FieldElement::zero());
+        CirclePoly::new(coeffs)
+    }
+
+    // the smallest size that holds every nonzero coefficient
+    fn trimmed(&self) -> Self {
+        let len = match self.coeffs.iter().rposition(|c| *c != FieldElement::zero()) {
+            Some(k) => (k + 1).next_power_of_two(),
+            None => 1,
+        };
+        self.truncate(len.ilog2())
+    }
+
+    // folds the coefficients pairwise with the basis factors y, x, 2x^2-1, ... of the point:
+    // pairs (2k, 2k+1) only differ by the factor y, then pairs of the result by x, and so on
+    pub fn eval_at_point<E: IsField>(&self, point: &CirclePoint<E>) -> FieldElement<E>
+    where
+        F: IsSubFieldOf<E>,
+    {
+        if self.coeffs.len() == 1 {
+            return self.coeffs[0].clone().to_extension();
+        }
+        let y = point.get_y();
+        let mut folded: Vec<FieldElement<E>> = self
+            .coeffs
+            .chunks_exact(2)
+            .map(|pair| &pair[0] + &pair[1] * &y)
+            .collect();
+        let mut x = point.get_x();
+        while folded.len() > 1 {
+            folded = folded
+                .chunks_exact(2)
+                .map(|pair| &pair[0] + &pair[1] * &x)
+                .collect();
+            x = x.square().double() - FieldElement::one();
+        }
+        folded.pop().unwrap()
+    }
+
+    // evaluations over any domain at least as large as the polynomial
+    // the basis of a smaller domain is a prefix of the basis of a larger one,
+    // so evaluating on a larger domain is padding the coefficients with zeros
+    pub fn evaluate(&self, domain: CircleDomain) -> CircleEvaluation<F> {
+        self.evaluate_with_twiddles(&TwiddleTree::for_domain(domain))
+    }
+
+    fn evaluate_with_twiddles(&self, twiddles: &TwiddleTree) -> CircleEvaluation<F> {
+        let domain = twiddles.domain();
+        assert!(domain.log_size() >= self.log_size());
+        let mut values = self.coeffs.clone();
+        values.resize(domain.size(), FieldElement::zero());
+        inv_fft(&mut values, twiddles);
+        bit_reverse(&mut values);
+        CircleEvaluation::new(domain, values)
+    }
+}
+
+impl<F: IsField + 'static> Add for CirclePoly<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    type Output = CirclePoly<F>;
+    fn add(self, rhs: Self) -> Self::Output {
+        let log_size = self.log_size().max(rhs.log_size());
+        let (lhs, rhs) = (self.extend(log_size), rhs.extend(log_size));
+        CirclePoly::new(lhs.coeffs.iter().zip(&rhs.coeffs).map(|(a, b)| a + b).collect())
+    }
+}
+
+impl<F: IsField + 'static> Neg for CirclePoly<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    type Output = CirclePoly<F>;
+    fn neg(self) -> Self::Output {
+        CirclePoly::new(self.coeffs.iter().map(|c| -c).collect())
+    }
+}
+
+impl<F: IsField + 'static> Sub for CirclePoly<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    type Output = CirclePoly<F>;
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + (-rhs)
+    }
+}
+
+// scalar multiplication
+impl<F: IsField + 'static> Mul<FieldElement<F>> for CirclePoly<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    type Output = CirclePoly<F>;
+    fn mul(self, rhs: FieldElement<F>) -> Self::Output {
+        CirclePoly::new(self.coeffs.iter().map(|c| c * &rhs).collect())
+    }
+}
+
+// product through evaluations: a domain of size 2^n holds every polynomial of degree < 2^(n-1)
+// plus one of degree 2^(n-1), so a domain larger than twice the degree of the product is needed
+impl<F: IsField + 'static> Mul for CirclePoly<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    type Output = CirclePoly<F>;
+    fn mul(self, rhs: Self) -> Self::Output {
+        let degree = self.degree() + rhs.degree();
+        let log_size = (2 * degree + 1).next_power_of_two().ilog2().max(1);
+        let domain = CanonicCoset::new(log_size).circle_domain();
+        let lhs = self.trimmed().evaluate(domain);
+        let rhs = rhs.trimmed().evaluate(domain);
+        let values = lhs.values.iter().zip(&rhs.values).map(|(a, b)| a * b).collect();
+        CircleEvaluation::new(domain, values).interpolate()
+    }
+}
+
+// the values of a polynomial over a CircleDomain, in the natural order of the domain
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircleEvaluation<F: IsField = M31> {
+    domain: CircleDomain,
+    values: Vec<FieldElement<F>>,
+}
+
+impl<F: IsField + 'static> CircleEvaluation<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    pub fn new(domain: CircleDomain, values: Vec<FieldElement<F>>) -> Self {
+        assert_eq!(domain.size(), values.len());
+        CircleEvaluation { domain, values }
+    }
+
+    pub fn domain(&self) -> CircleDomain {
+        self.domain
+    }
+
+    pub fn values(&self) -> &[FieldElement<F>] {
+        &self.values
+    }
+
+    pub fn interpolate(&self) -> CirclePoly<F> {
+        interpolate_with_twiddles(self.values.clone(), &TwiddleTree::for_domain(self.domain))
+    }
+
+    // value of the interpolant at any point, without interpolating (see fast_fft::bary_eval)
+    pub fn eval_at_point<E: IsField>(&self, point: &CirclePoint<E>) -> FieldElement<E>
+    where
+        M31: IsSubFieldOf<E>,
+        F: IsSubFieldOf<E>,
+    {
+        let mut values = self.values.clone();
+        bit_reverse(&mut values);
+        bary_eval(&values, point, &TwiddleTree::for_domain(self.domain))
+    }
+}
+
+// low degree extension of a trace column given over its canonic domain:
+// interpolates it and evaluates the result over the canonic domain 2^log_blowup times larger
+// the two domains are disjoint, the extension agrees with the trace as a polynomial
+pub fn extend<F: IsField + 'static>(evaluation: &CircleEvaluation<F>, log_blowup: u32) -> CircleEvaluation<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    assert!(evaluation.domain().is_canonic(), "trace must be on a canonic domain");
+    assert!((1..=MAX_LOG_BLOWUP).contains(&log_blowup));
+    let log_size = evaluation.domain().log_size() + log_blowup;
+    evaluation
+        .interpolate()
+        .evaluate(CanonicCoset::new(log_size).circle_domain())
+}
+
+fn interpolate_with_twiddles<F: IsField + 'static>(
+    values: Vec<FieldElement<F>>,
+    twiddles: &TwiddleTree,
+) -> CirclePoly<F>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    let mut coeffs = values;
+    bit_reverse(&mut coeffs);
+    fft(&mut coeffs, twiddles);
+    CirclePoly::new(coeffs)
+}
+
+// batch versions for traces stored column major, columns[c][i] being the value of column c
+// at domain.at(i): one twiddle tree is shared by all the columns, which are transformed in
+// parallel with the parallel feature
+pub fn interpolate_columns<F: IsField + 'static>(
+    domain: CircleDomain,
+    columns: &[Vec<FieldElement<F>>],
+) -> Vec<CirclePoly<F>>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    let twiddles = TwiddleTree::for_domain(domain);
+    #[cfg(feature = "parallel")]
+    let iter = columns.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let iter = columns.iter();
+    iter.map(|column| interpolate_with_twiddles(column.clone(), &twiddles))
+        .collect()
+}
+
+pub fn evaluate_columns<F: IsField + 'static>(
+    polys: &[CirclePoly<F>],
+    domain: CircleDomain,
+) -> Vec<CircleEvaluation<F>>
+where
+    M31: IsSubFieldOf<F>,
+    FieldElement<F>: Send + Sync,
+{
+    let twiddles = TwiddleTree::for_domain(domain);
+    #[cfg(feature = "parallel")]
+    let iter = polys.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let iter = polys.iter();
+    iter.map(|poly| poly.evaluate_with_twiddles(&twiddles))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qm31::{QM31Impl, QM31};
+    use crate::utils::random_values;
+
+    fn trace(log_size: u32, seed: u64) -> CircleEvaluation<M31> {
+        let domain = CanonicCoset::new(log_size).circle_domain();
+        CircleEvaluation::new(domain, random_values(domain.size(), seed))
+    }
+
+    #[test]
+    fn evaluate_and_interpolate_agree_with_fft() {
+        let domain = CanonicCoset::new(7).circle_domain();
+        let coeffs = random_values(1 << 7, 1);
+        let evaluation = CirclePoly::new(coeffs.clone()).evaluate(domain);
+        assert_eq!(evaluation.values(), crate::fft::inv_fft(&coeffs, Some(&domain)));
+        assert_eq!(evaluation.interpolate().coeffs(), coeffs);
+    }
+
+    #[test]
+    fn eval_at_point_matches_the_domain_and_the_extension() {
+        let trace = trace(6, 3);
+        for i in 0..trace.values().len() {
+            assert_eq!(trace.eval_at_point(&trace.domain().at(i)), trace.values()[i]);
+        }
+        // an out of domain point over QM31 gives the same value from the trace and from its LDE
+        let t = FieldElement::<QM31>::from_u32s(5, 6, 7, 8);
+        let point = CirclePoint::<QM31>::from_stereographic(t);
+        let extended = extend(&trace, 2);
+        assert_eq!(trace.eval_at_point(&point), extended.eval_at_point(&point));
+    }
+
+    #[test]
+    fn arithmetic_matches_point_evaluation() {
+        let t = FieldElement::<QM31>::from_u32s(1, 2, 3, 4);
+        let point = CirclePoint::<QM31>::from_stereographic(t);
+        let a = CirclePoly::new(random_values(32, 1));
+        let b = CirclePoly::new(random_values(8, 2));
+        let (va, vb) = (a.eval_at_point(&point), b.eval_at_point(&point));
+        let scalar = FieldElement::<M31>::from(7);
+
+        assert_eq!((a.clone() + b.clone()).eval_at_point(&point), va + vb);
+        assert_eq!((a.clone() - b.clone()).eval_at_point(&point), va - vb);
+        assert_eq!((a.clone() * scalar).eval_at_point(&point), scalar * va);
+        let product = a.clone() * b.clone();
+        assert_eq!(product.degree(), a.degree() + b.degree());
+        assert_eq!(product.eval_at_point(&point), va * vb);
+    }
+
+    #[test]
+    fn degree_extend_and_truncate() {
+        let poly = CirclePoly::new(random_values(16, 4));
+        assert_eq!(poly.degree(), 8);
+        assert_eq!(poly.truncate(3).degree(), 4);
+        let extended = poly.extend(6);
+        assert_eq!(extended.log_size(), 6);
+        assert_eq!(extended.degree(), 8);
+        assert_eq!(extended.truncate(4), poly);
+        let point = CanonicCoset::new(9).at(3);
+        assert_eq!(extended.eval_at_point(&point), poly.eval_at_point(&point));
+        // the square of a full polynomial needs the whole next domain
+        assert_eq!((poly.clone() * poly.clone()).degree(), 16);
+        assert_eq!((poly.clone() * poly).log_size(), 6);
+    }
+
+    #[test]
+    fn extension_agrees_with_the_trace() {
+        for log_size in 1..=8 {
+            let trace = trace(log_size, log_size as u64);
+            for log_blowup in 1..=MAX_LOG_BLOWUP {
+                let extended = extend(&trace, log_blowup);
+                assert_eq!(extended.domain().log_size(), log_size + log_blowup);
+                assert!(extended.domain().is_canonic());
+
+                // still the same polynomial: no coefficient above the trace size
+                let poly = extended.interpolate();
+                let (low, high) = poly.coeffs().split_at(trace.values().len());
+                assert!(high.iter().all(|c| *c == FieldElement::zero()));
+                assert_eq!(CirclePoly::new(low.to_vec()).evaluate(trace.domain()), trace);
+            }
+        }
+    }
+
+    #[test]
+    fn batch_transforms_match_single_columns() {
+        let domain = CanonicCoset::new(8).circle_domain();
+        let columns: Vec<Vec<FieldElement<M31>>> =
+            (0..10).map(|c| random_values(domain.size(), c)).collect();
+        let polys = interpolate_columns(domain, &columns);
+        let big_domain = CanonicCoset::new(10).circle_domain();
+        let extended = evaluate_columns(&polys, big_domain);
+        for ((column, poly), evaluation) in columns.iter().zip(&polys).zip(&extended) {
+            let single = CircleEvaluation::new(domain, column.clone());
+            assert_eq!(*poly, single.interpolate());
+            assert_eq!(*evaluation, extend(&single, 2));
+        }
+    }
+
+    #[test]
+    fn extension_of_a_constant_is_constant() {
+        let domain = CanonicCoset::new(5).circle_domain();
+        let value = FieldElement::<M31>::from(42);
+        let extended = extend(&CircleEvaluation::new(domain, vec![value; 32]), 3);
+        assert!(extended.values().iter().all(|v| *v == value));
+    }
+
+    #[test]
+    fn extends_extension_field_columns() {
+        let domain = CanonicCoset::new(4).circle_domain();
+        let values: Vec<FieldElement<QM31>> = random_values(4 * 16, 9)
+            .chunks(4)
+            .map(|c| FieldElement::<QM31>::from_m31(c[0], c[1], c[2], c[3]))
+            .collect();
+        let trace = CircleEvaluation::new(domain, values);
+        let extended = extend(&trace, 2);
+        let mut coeffs = extended.interpolate().coeffs().to_vec();
+        coeffs.truncate(16);
+        assert_eq!(CirclePoly::new(coeffs).evaluate(domain), trace);
+    }
+}
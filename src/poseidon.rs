@@ -1,52 +1,37 @@
 use lambdaworks_math::field::element::FieldElement;
 use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
 
-use crate::precomputes::inverse_x;
-use crate::utils::{log2, mk_junk_data, reshape, reverse_bit_order};
+use crate::utils::{mk_junk_data, reshape};
+use std::sync::OnceLock;
 // from zorch.m31 import (
 //     M31, ExtendedM31, Point, modulus, zeros_like, Z, G, matmul
 // )
-use crate::precomputes::{inverse_x, inverse_y, rbos, sub_domains};
 
-// Global declaration of round_constants
+// 16 wide permutation of the python original: 4 full rounds, 56 partial rounds that only
+// raise state[0] to the 5th power and mix with diag(innerdiag) + ones, then 4 full rounds.
+// Used by the poseidon channel and hasher
+//
+// this is the repo's Poseidon, not Poseidon2-M31, and doesn't match other Poseidon2
+// implementations: its layers have the shapes of Poseidon2's (blocks of M4 with 2*M4 on the
+// diagonal, and diag + ones in the partial rounds), but there is no linear layer before the
+// first round, the state multiplies the matrix from the left (applying M4 transposed), there
+// are 56 partial rounds instead of 14, and the constants and innerdiag are mk_junk_data
+// rather than generated as the Poseidon2 paper specifies
+pub const WIDTH: usize = 16;
+const N_ROUNDS: usize = 64;
+const N_HALF_FULL_ROUNDS: usize = 4;
+
 pub fn generate_round_constants() -> Vec<Vec<FieldElement<M31>>> {
-    let length = 1024;
+    let length = (N_ROUNDS * WIDTH) as u32;
     let junk_data = mk_junk_data(length);
-    reshape(junk_data, 64, 16)
+    reshape(junk_data, N_ROUNDS, WIDTH)
 }
 
 pub fn mds44() -> Vec<Vec<FieldElement<M31>>> {
-    [
-        [
-            FieldElement::new(5),
-            FieldElement::new(7),
-            FieldElement::new(5),
-            FieldElement::new(5),
-        ]
-        .to_vec(),
-        [
-            FieldElement::new(5),
-            FieldElement::new(5),
-            FieldElement::new(5),
-            FieldElement::new(5),
-        ]
-        .to_vec(),
-        [
-            FieldElement::new(5),
-            FieldElement::new(5),
-            FieldElement::new(5),
-            FieldElement::new(5),
-        ]
-        .to_vec(),
-        [
-            FieldElement::new(5),
-            FieldElement::new(5),
-            FieldElement::new(5),
-            FieldElement::new(5),
-        ]
-        .to_vec(),
-    ]
-    .to_vec()
+    [[5, 7, 1, 3], [4, 6, 1, 1], [1, 3, 5, 7], [1, 1, 4, 6]]
+        .iter()
+        .map(|row| row.iter().map(|v| FieldElement::new(*v)).collect())
+        .collect()
 }
 
 pub fn mds_matrix(mds44: Vec<Vec<FieldElement<M31>>>) -> Vec<Vec<FieldElement<M31>>> {
@@ -70,18 +55,71 @@ pub fn mds_matrix(mds44: Vec<Vec<FieldElement<M31>>>) -> Vec<Vec<FieldElement<M3
     mds
 }
 
+// the partial round matrix is mdsinner = diag(innerdiag) + ones
 pub fn generate_innerdiag_and_mdsinner() -> (Vec<Vec<FieldElement<M31>>>, Vec<FieldElement<M31>>) {
-    let innerdiag = mk_junk_data(16)
-        .into_iter()
-        .map(|val| FieldElement::new(val + 1))
-        .collect::<Vec<_>>();
+    let innerdiag = mk_junk_data(16);
 
-    let mut mdsinner = [[FieldElement::<M31>::new(1); 16]; 16];
+    let mut mdsinner = vec![vec![FieldElement::<M31>::one(); 16]; 16];
     for i in 0..16 {
-        mdsinner[i][i] = innerdiag[i].clone();
+        mdsinner[i][i] = innerdiag[i] + FieldElement::one();
     }
 
-    (mdsinner, innerdiag.try_into().unwrap())
+    (mdsinner, innerdiag)
+}
+
+struct Params {
+    round_constants: Vec<Vec<FieldElement<M31>>>,
+    mds: Vec<Vec<FieldElement<M31>>>,
+    innerdiag: Vec<FieldElement<M31>>,
+}
+
+fn params() -> &'static Params {
+    static PARAMS: OnceLock<Params> = OnceLock::new();
+    PARAMS.get_or_init(|| Params {
+        round_constants: generate_round_constants(),
+        mds: mds_matrix(mds44()),
+        innerdiag: generate_innerdiag_and_mdsinner().1,
+    })
+}
+
+fn pow5(x: FieldElement<M31>) -> FieldElement<M31> {
+    x.square().square() * x
+}
+
+pub fn permute(state: &mut [FieldElement<M31>; WIDTH]) {
+    let params = params();
+    for i in 0..N_ROUNDS {
+        let rc = &params.round_constants[i];
+        if (N_HALF_FULL_ROUNDS..N_ROUNDS - N_HALF_FULL_ROUNDS).contains(&i) {
+            state[0] = pow5(state[0] + rc[0]);
+            let sum = state.iter().fold(FieldElement::zero(), |acc, s| acc + s);
+            for (s, d) in state.iter_mut().zip(&params.innerdiag) {
+                *s = *s * d + sum;
+            }
+        } else {
+            let input: Vec<FieldElement<M31>> =
+                state.iter().zip(rc).map(|(s, c)| pow5(s + c)).collect();
+            // state = input * mds (input as a row vector)
+            for (j, s) in state.iter_mut().enumerate() {
+                *s = input
+                    .iter()
+                    .zip(&params.mds)
+                    .fold(FieldElement::zero(), |acc, (v, row)| acc + v * row[j]);
+            }
+        }
+    }
+}
+
+// compression of two 8 element digests into one, poseidon_hash(in1, in2) of the python original
+pub fn poseidon_hash(
+    in1: &[FieldElement<M31>; 8],
+    in2: &[FieldElement<M31>; 8],
+) -> [FieldElement<M31>; 8] {
+    let mut state = [FieldElement::zero(); WIDTH];
+    state[..8].copy_from_slice(in1);
+    state[8..].copy_from_slice(in2);
+    permute(&mut state);
+    std::array::from_fn(|i| state[8 + i] + in2[i])
 }
 
 // import numpy
@@ -283,3 +321,53 @@ pub fn generate_innerdiag_and_mdsinner() -> (Vec<Vec<FieldElement<M31>>>, Vec<Fi
 //         * (next_state[8:16] - state[184:192])
 //     )
 //     return o
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m31s<const N: usize>(values: [u32; N]) -> [FieldElement<M31>; N] {
+        values.map(FieldElement::new)
+    }
+
+    // known answers from running poseidon_hash_cpu above (ported to plain python ints), so
+    // they pin this permutation to the python original, not to Poseidon2
+    #[test]
+    fn permute_matches_the_python_original() {
+        let mut state = m31s(std::array::from_fn(|i| i as u32));
+        permute(&mut state);
+        let expected = m31s([
+            544439906, 780570564, 256442599, 2030166207, 953222932, 1968566733, 821042322,
+            543775945, 145763240, 1710518347, 1538638235, 1241216632, 327416661, 2146027681,
+            336828848, 1244624080,
+        ]);
+        assert_eq!(state, expected);
+
+        let mut state = [FieldElement::zero(); WIDTH];
+        permute(&mut state);
+        let expected = m31s([
+            270884302, 1895108035, 1069541212, 933890453, 408555961, 254031572, 453626237,
+            816895089, 586674201, 2131902482, 391830676, 1546643835, 943992766, 1324705147,
+            1053925315, 2062718019,
+        ]);
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn poseidon_hash_matches_the_python_original() {
+        let in1 = m31s(std::array::from_fn(|i| i as u32));
+        let in2 = m31s(std::array::from_fn(|i| 8 + i as u32));
+        let expected = m31s([
+            145763248, 1710518356, 1538638245, 1241216643, 327416673, 2146027694, 336828862,
+            1244624095,
+        ]);
+        assert_eq!(poseidon_hash(&in1, &in2), expected);
+
+        let zero = [FieldElement::zero(); 8];
+        let expected = m31s([
+            586674201, 2131902482, 391830676, 1546643835, 943992766, 1324705147, 1053925315,
+            2062718019,
+        ]);
+        assert_eq!(poseidon_hash(&zero, &zero), expected);
+    }
+}
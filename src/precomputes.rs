@@ -1,107 +1,120 @@
-use crate::circle::{CircleImpl, CirclePoint, G, MODULUS};
-use crate::utils::log2;
-use crate::utils::{folded_reverse_bit_order, reverse_bit_order};
+use crate::circle::CircleImpl;
+use crate::domain::{CanonicCoset, CircleDomain};
+use crate::utils::{batch_inverse_in_place, bit_reverse};
 use lambdaworks_math::field::element::FieldElement;
 use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
-use ndarray::Array;
-
-const TOP_DOMAIN_SIZE: u32 = 1 << 24; // 2**24
-const LOG2_TOP_DOMAIN_SIZE: usize = 24;
-
-//  Generator point
-pub fn generator_point(G: CirclePoint) -> CirclePoint {
-    let mut ans: CirclePoint = G;
-    for _ in LOG2_TOP_DOMAIN_SIZE..log2(MODULUS + 1) - 1 {
-        ans = ans.double();
-    }
-    ans
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+// twiddles of the in place circle fft over a CircleDomain of size n, whose values are
+// stored in bit reversed order
+//
+// the first (circle) layer pairs positions 2k, 2k+1, which hold a point of the half coset
+// and its conjugate, and uses the y of that point
+// line layer l pairs positions that differ by 2^l and uses the x of the points of the half
+// coset doubled l-1 times, again in bit reversed order
+pub struct TwiddleTree {
+    domain: CircleDomain,
+    y_twiddles: Vec<FieldElement<M31>>,
+    // all line layers one after the other, layer 1 first: n/4 + n/8 + ... + 1 values
+    x_twiddles: Vec<FieldElement<M31>>,
+    inv_y_twiddles: Vec<FieldElement<M31>>,
+    inv_x_twiddles: Vec<FieldElement<M31>>,
 }
 
-// Get the subdomains
-pub fn get_subdomains() -> Vec<CirclePoint> {
-    let mut top_domain = CirclePoint::zeroes(2);
-    top_domain[1] = G();
-    for i in 1..log2(TOP_DOMAIN_SIZE * 2) {
-        let doubled: Vec<CirclePoint> = top_domain.iter().map(|x| x.double()).collect();
-
-        let mut new_domain = CirclePoint::zeroes(1 << (i + 1));
-
-        // Fill even indices with doubled points
-        for (idx, point) in doubled.iter().enumerate() {
-            new_domain[idx * 2] = point.clone();
+impl TwiddleTree {
+    pub fn new(domain: CircleDomain) -> Self {
+        let half_coset = domain.half_coset();
+
+        // the points are walked in coset order, one addition each, and then bit reversed
+        let mut y_twiddles: Vec<FieldElement<M31>> = half_coset.iter().map(|p| p.get_y()).collect();
+        bit_reverse(&mut y_twiddles);
+
+        let mut x_twiddles = Vec::with_capacity(half_coset.size());
+        let mut line_coset = half_coset;
+        while line_coset.log_size() > 0 {
+            let mut layer: Vec<FieldElement<M31>> =
+                line_coset.iter().take(line_coset.size() / 2).map(|p| p.get_x()).collect();
+            bit_reverse(&mut layer);
+            x_twiddles.extend(layer);
+            line_coset = line_coset.double();
         }
 
-        // Fill odd indices with doubled points + G
-        for (idx, point) in doubled.iter().enumerate() {
-            new_domain[idx * 2 + 1] = point.clone() + G().clone();
-        }
+        let mut inverses = [y_twiddles.clone(), x_twiddles.clone()].concat();
+        batch_inverse_in_place(&mut inverses).expect("twiddles are never zero");
+        let inv_x_twiddles = inverses.split_off(y_twiddles.len());
 
-        top_domain = new_domain;
+        TwiddleTree {
+            domain,
+            y_twiddles,
+            x_twiddles,
+            inv_y_twiddles: inverses,
+            inv_x_twiddles,
+        }
     }
 
-    //  only odd-indexed points
-    top_domain = top_domain.into_iter().step_by(2).collect();
-
-    let mut sub_domains = CirclePoint::zeroes((TOP_DOMAIN_SIZE as usize) * 2);
-
-    // Copy the top domain into the second half of sub_domains
-    sub_domains[(TOP_DOMAIN_SIZE as usize)..].clone_from_slice(&top_domain);
+    // twiddles of the domain a trace of size 2^log_size is interpolated on
+    pub fn canonic(log_size: u32) -> Self {
+        TwiddleTree::new(CanonicCoset::new(log_size).circle_domain())
+    }
 
-    // Iterate backwards through the domain sizes
-    for i in (0..=log2(TOP_DOMAIN_SIZE) - 1).rev() {
-        let start_idx = 1 << i;
-        let end_idx = 1 << (i + 1);
-        let source_start = end_idx.clone();
-        let source_end = 1 << (i + 3) * 3;
+    // same as canonic, but built once per size and shared by every later caller (and thread)
+    // the line layers of a canonic tree are the first line layers of the smaller canonic trees:
+    // x_twiddles(layer) of cached(log_size) equal x_twiddles(1) of cached(log_size - layer + 1)
+    pub fn cached(log_size: u32) -> Arc<TwiddleTree> {
+        static CACHE: OnceLock<Mutex<HashMap<u32, Arc<TwiddleTree>>>> = OnceLock::new();
+        let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+        if let Some(tree) = cache.lock().unwrap().get(&log_size) {
+            return tree.clone();
+        }
+        // built without holding the lock, if two threads race the first one to finish wins
+        let tree = Arc::new(TwiddleTree::canonic(log_size));
+        cache
+            .lock()
+            .unwrap()
+            .entry(log_size)
+            .or_insert(tree)
+            .clone()
+    }
 
-        // Get the source slice and double it
-        let doubled: Vec<CirclePoint> = sub_domains[source_start..source_end]
-            .iter()
-            .map(|x| x.double())
-            .collect();
-        // .collect::Vec<_>();
+    // the cached tree of a canonic domain, and a new one for any other domain
+    pub fn for_domain(domain: CircleDomain) -> Arc<TwiddleTree> {
+        if domain.is_canonic() {
+            TwiddleTree::cached(domain.log_size())
+        } else {
+            Arc::new(TwiddleTree::new(domain))
+        }
+    }
 
-        // Copy the doubled points to their destination
-        sub_domains[start_idx..end_idx].clone_from_slice(&doubled);
+    pub fn domain(&self) -> CircleDomain {
+        self.domain
     }
 
-    sub_domains
-}
+    pub fn log_size(&self) -> u32 {
+        self.domain.log_size()
+    }
 
-pub fn inverse_x(sub_domains: Vec<CirclePoint>) -> Vec<FieldElement<M31>> {
-    sub_domains.iter().map(|c| c.inverse_x()).collect()
-}
+    pub fn y_twiddles(&self) -> &[FieldElement<M31>] {
+        &self.y_twiddles
+    }
 
-pub fn inverse_y(sub_domains: Vec<CirclePoint>) -> Vec<FieldElement<M31>> {
-    sub_domains.iter().map(|c| c.inverse_y()).collect()
-}
+    pub fn inv_y_twiddles(&self) -> &[FieldElement<M31>] {
+        &self.inv_y_twiddles
+    }
 
-pub fn compute_bit_orders() -> (Vec<u32>, Vec<u32>) {
-    // Initialize arrays
-    let mut rbos = vec![0u32; TOP_DOMAIN_SIZE as usize * 2];
-    let mut folded_rbos = vec![0u32; TOP_DOMAIN_SIZE as usize * 2];
-
-    // Compute RBO (Reversed Bit Order)
-    for i in 0..LOG2_TOP_DOMAIN_SIZE {
-        let start = 1 << i;
-        let end = 1 << (i + 1);
-        let range: Vec<u32> = (0..2u32.pow(i as u32)).collect();
-        let ndarray_range: Array<u32,_> = Array::from(range);
-        let rbo_slice = reverse_bit_order(&ndarray_range);
-        let slice = rbo_slice.as_slice().unwrap();
-        rbos[start..end].copy_from_slice(&slice);
+    // twiddles of line layer `layer`, for 1 <= layer < log_size
+    pub fn x_twiddles(&self, layer: u32) -> &[FieldElement<M31>] {
+        &self.x_twiddles[self.x_layer_range(layer)]
     }
 
-    // Compute Folded RBO
-    for i in 0..LOG2_TOP_DOMAIN_SIZE {
-        let start = 1 << i;
-        let end = 1 << (i + 1);
-        let range: Vec<u32> = (0..2u32.pow(i as u32)).collect();
-        let ndarray_range: Array<u32,_> = Array::from(range);
-        let folded_rbo_slice = folded_reverse_bit_order(&ndarray_range);
-        let folded_slice = folded_rbo_slice.as_slice().unwrap();
-        folded_rbos[start..end].copy_from_slice(&folded_slice);
+    pub fn inv_x_twiddles(&self, layer: u32) -> &[FieldElement<M31>] {
+        &self.inv_x_twiddles[self.x_layer_range(layer)]
     }
 
-    (rbos, folded_rbos)
+    fn x_layer_range(&self, layer: u32) -> std::ops::Range<usize> {
+        assert!(layer >= 1 && layer < self.log_size());
+        let size = self.domain.size();
+        let start = size / 2 - (size >> layer);
+        start..start + (size >> (layer + 1))
+    }
 }
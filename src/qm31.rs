@@ -0,0 +1,277 @@
+use crate::cm31::{CM31Impl, CM31};
+use lambdaworks_math::field::element::FieldElement;
+use lambdaworks_math::field::errors::FieldError;
+use lambdaworks_math::field::fields::mersenne31::field::Mersenne31Field as M31;
+use lambdaworks_math::field::traits::{IsField, IsSubFieldOf};
+
+// degree 4 extension of M31, built as CM31[u] / (u^2 - (2 + i))
+// elements are a + b*u with a, b in CM31, so the field has p^4 ~ 2^124 elements
+// and all the random challenges of the protocol should be drawn from here
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
+pub struct QM31;
+
+// u^2 = R = 2 + i
+pub fn r() -> FieldElement<CM31> {
+    FieldElement::<CM31>::from_u32s(2, 1)
+}
+
+// u^p = u * (2 + i)^((p-1)/2)
+fn frobenius_u() -> FieldElement<CM31> {
+    FieldElement::<CM31>::from_u32s(21189756, 42379512)
+}
+
+impl IsField for QM31 {
+    // [a, b] represents a + b*u
+    type BaseType = [FieldElement<CM31>; 2];
+
+    fn add(a: &Self::BaseType, b: &Self::BaseType) -> Self::BaseType {
+        [a[0] + b[0], a[1] + b[1]]
+    }
+
+    // (a0 + a1*u)(b0 + b1*u) = a0*b0 + R*a1*b1 + (a0*b1 + a1*b0)*u
+    fn mul(a: &Self::BaseType, b: &Self::BaseType) -> Self::BaseType {
+        let a0b0 = a[0] * b[0];
+        let a1b1 = a[1] * b[1];
+        let cross = (a[0] + a[1]) * (b[0] + b[1]);
+        [a0b0 + r() * a1b1, cross - a0b0 - a1b1]
+    }
+
+    fn square(a: &Self::BaseType) -> Self::BaseType {
+        [a[0].square() + r() * a[1].square(), (a[0] * a[1]).double()]
+    }
+
+    fn sub(a: &Self::BaseType, b: &Self::BaseType) -> Self::BaseType {
+        [a[0] - b[0], a[1] - b[1]]
+    }
+
+    fn neg(a: &Self::BaseType) -> Self::BaseType {
+        [-a[0], -a[1]]
+    }
+
+    // 1/(a + b*u) = (a - b*u)/(a^2 - R*b^2)
+    fn inv(a: &Self::BaseType) -> Result<Self::BaseType, FieldError> {
+        let denom_inv = (a[0].square() - r() * a[1].square()).inv()?;
+        Ok([a[0] * denom_inv, -a[1] * denom_inv])
+    }
+
+    fn div(a: &Self::BaseType, b: &Self::BaseType) -> Self::BaseType {
+        <Self as IsField>::mul(a, &Self::inv(b).unwrap())
+    }
+
+    fn eq(a: &Self::BaseType, b: &Self::BaseType) -> bool {
+        a[0] == b[0] && a[1] == b[1]
+    }
+
+    fn zero() -> Self::BaseType {
+        [FieldElement::zero(), FieldElement::zero()]
+    }
+
+    fn one() -> Self::BaseType {
+        [FieldElement::one(), FieldElement::zero()]
+    }
+
+    fn from_u64(x: u64) -> Self::BaseType {
+        [FieldElement::from(x), FieldElement::zero()]
+    }
+
+    fn from_base_type(x: Self::BaseType) -> Self::BaseType {
+        x
+    }
+}
+
+// mixed base x extension arithmetic, FieldElement<M31> * FieldElement<QM31>
+// only touches the 4 coordinates instead of doing a full extension multiplication
+impl IsSubFieldOf<QM31> for M31 {
+    fn mul(a: &u32, b: &[FieldElement<CM31>; 2]) -> [FieldElement<CM31>; 2] {
+        let a = FieldElement::<M31>::from_raw(*a);
+        [a * b[0], a * b[1]]
+    }
+
+    fn add(a: &u32, b: &[FieldElement<CM31>; 2]) -> [FieldElement<CM31>; 2] {
+        [FieldElement::<M31>::from_raw(*a) + b[0], b[1]]
+    }
+
+    fn div(a: &u32, b: &[FieldElement<CM31>; 2]) -> [FieldElement<CM31>; 2] {
+        let b_inv = QM31::inv(b).unwrap();
+        <M31 as IsSubFieldOf<QM31>>::mul(a, &b_inv)
+    }
+
+    fn sub(a: &u32, b: &[FieldElement<CM31>; 2]) -> [FieldElement<CM31>; 2] {
+        [FieldElement::<M31>::from_raw(*a) - b[0], -b[1]]
+    }
+
+    fn embed(a: u32) -> [FieldElement<CM31>; 2] {
+        [
+            FieldElement::<M31>::from_raw(a).to_extension(),
+            FieldElement::zero(),
+        ]
+    }
+
+    fn to_subfield_vec(b: [FieldElement<CM31>; 2]) -> Vec<u32> {
+        b.iter()
+            .flat_map(|c| [c.real().to_raw(), c.imaginary().to_raw()])
+            .collect()
+    }
+}
+
+impl IsSubFieldOf<QM31> for CM31 {
+    fn mul(a: &[FieldElement<M31>; 2], b: &[FieldElement<CM31>; 2]) -> [FieldElement<CM31>; 2] {
+        let a = FieldElement::<CM31>::from_raw(*a);
+        [a * b[0], a * b[1]]
+    }
+
+    fn add(a: &[FieldElement<M31>; 2], b: &[FieldElement<CM31>; 2]) -> [FieldElement<CM31>; 2] {
+        [FieldElement::<CM31>::from_raw(*a) + b[0], b[1]]
+    }
+
+    fn div(a: &[FieldElement<M31>; 2], b: &[FieldElement<CM31>; 2]) -> [FieldElement<CM31>; 2] {
+        let b_inv = QM31::inv(b).unwrap();
+        <CM31 as IsSubFieldOf<QM31>>::mul(a, &b_inv)
+    }
+
+    fn sub(a: &[FieldElement<M31>; 2], b: &[FieldElement<CM31>; 2]) -> [FieldElement<CM31>; 2] {
+        [FieldElement::<CM31>::from_raw(*a) - b[0], -b[1]]
+    }
+
+    fn embed(a: [FieldElement<M31>; 2]) -> [FieldElement<CM31>; 2] {
+        [FieldElement::from_raw(a), FieldElement::zero()]
+    }
+
+    fn to_subfield_vec(b: [FieldElement<CM31>; 2]) -> Vec<[FieldElement<M31>; 2]> {
+        b.iter().map(|c| *c.value()).collect()
+    }
+}
+
+pub trait QM31Impl {
+    fn from_cm31(a: FieldElement<CM31>, b: FieldElement<CM31>) -> Self;
+    fn from_m31(
+        a: FieldElement<M31>,
+        b: FieldElement<M31>,
+        c: FieldElement<M31>,
+        d: FieldElement<M31>,
+    ) -> Self;
+    fn from_u32s(a: u32, b: u32, c: u32, d: u32) -> Self;
+    fn to_m31_array(&self) -> [FieldElement<M31>; 4];
+    fn frobenius(&self) -> Self;
+}
+
+impl QM31Impl for FieldElement<QM31> {
+    fn from_cm31(a: FieldElement<CM31>, b: FieldElement<CM31>) -> Self {
+        FieldElement::from_raw([a, b])
+    }
+
+    // (a + b*i) + (c + d*i)*u
+    fn from_m31(
+        a: FieldElement<M31>,
+        b: FieldElement<M31>,
+        c: FieldElement<M31>,
+        d: FieldElement<M31>,
+    ) -> Self {
+        Self::from_cm31(
+            FieldElement::<CM31>::from_m31(a, b),
+            FieldElement::<CM31>::from_m31(c, d),
+        )
+    }
+
+    fn from_u32s(a: u32, b: u32, c: u32, d: u32) -> Self {
+        Self::from_m31(
+            FieldElement::new(a),
+            FieldElement::new(b),
+            FieldElement::new(c),
+            FieldElement::new(d),
+        )
+    }
+
+    fn to_m31_array(&self) -> [FieldElement<M31>; 4] {
+        let [a, b] = self.value();
+        [a.real(), a.imaginary(), b.real(), b.imaginary()]
+    }
+
+    // x -> x^p, the generator of Gal(QM31/M31)
+    // (a + b*u)^p = conj(a) + conj(b) * u^p
+    fn frobenius(&self) -> Self {
+        let [a, b] = self.value();
+        Self::from_cm31(a.conjugate(), b.conjugate() * frobenius_u())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circle::MODULUS;
+    use crate::utils::{check_field_axioms, random_values};
+
+    fn random_qm31s(n: usize, seed: u64) -> Vec<FieldElement<QM31>> {
+        random_values(4 * n, seed)
+            .chunks(4)
+            .map(|c| FieldElement::<QM31>::from_m31(c[0], c[1], c[2], c[3]))
+            .collect()
+    }
+
+    #[test]
+    fn is_a_field() {
+        let mut values = random_qm31s(50, 1);
+        // 0, 1, u and p - 1 in each coordinate, between random values
+        let p = MODULUS - 1;
+        let edges = [[0, 0, 0, 0], [1, 0, 0, 0], [0, 0, 1, 0], [p, 0, 0, 0], [0, p, 0, 0], [0, 0, p, 0], [0, 0, 0, p]];
+        for (k, [a, b, c, d]) in edges.into_iter().enumerate() {
+            values.insert(3 * k + 1, FieldElement::<QM31>::from_u32s(a, b, c, d));
+        }
+        check_field_axioms(&values);
+
+        let u = FieldElement::<QM31>::from_u32s(0, 0, 1, 0);
+        assert_eq!(u.square(), FieldElement::from_cm31(r(), FieldElement::zero()));
+    }
+
+    #[test]
+    fn inverts_every_nonzero_value() {
+        for x in random_qm31s(50, 2) {
+            assert_eq!(x * x.inv().unwrap(), FieldElement::one());
+        }
+        // a^2 - R*b^2 is never 0 for a nonzero value, R = 2 + i not being a square in CM31
+        let u = FieldElement::<QM31>::from_u32s(0, 0, 1, 0);
+        assert_eq!(u * u.inv().unwrap(), FieldElement::one());
+        assert!(matches!(FieldElement::<QM31>::zero().inv(), Err(FieldError::InvZeroError)));
+    }
+
+    #[test]
+    fn frobenius_is_x_to_the_p_of_order_4() {
+        for x in random_qm31s(20, 3) {
+            let (f1, f2) = (x.frobenius(), x.frobenius().frobenius());
+            assert_eq!(f1, x.pow(MODULUS));
+            // neither x^p nor x^(p^2) fixes a value outside CM31, but x^(p^4) does
+            assert_ne!(f1, x);
+            assert_ne!(f2, x);
+            assert_eq!(f2.frobenius().frobenius(), x);
+        }
+        // on CM31 it is the conjugation, and M31 is fixed
+        let c = FieldElement::<CM31>::from_u32s(3, 4);
+        let c_ext = FieldElement::<QM31>::from_cm31(c, FieldElement::zero());
+        assert_eq!(c_ext.frobenius(), FieldElement::from_cm31(c.conjugate(), FieldElement::zero()));
+        let m = FieldElement::<QM31>::from_u32s(5, 0, 0, 0);
+        assert_eq!(m.frobenius(), m);
+    }
+
+    #[test]
+    fn mixed_operations_match_the_extension() {
+        let m31s = random_values(20, 4);
+        let cm31s: Vec<FieldElement<CM31>> = random_values(40, 5)
+            .chunks(2)
+            .map(|c| FieldElement::<CM31>::from_m31(c[0], c[1]))
+            .collect();
+        let qm31s = random_qm31s(20, 6);
+        for ((a, c), b) in m31s.iter().zip(&cm31s).zip(qm31s) {
+            let a_ext = a.to_extension::<QM31>();
+            assert_eq!(a * b, a_ext * b);
+            assert_eq!(a + b, a_ext + b);
+            assert_eq!(a - b, a_ext - b);
+            assert_eq!(a / b, a_ext / b);
+
+            let c_ext = c.to_extension::<QM31>();
+            assert_eq!(c * b, c_ext * b);
+            assert_eq!(c + b, c_ext + b);
+            assert_eq!(c - b, c_ext - b);
+            assert_eq!(c / b, c_ext / b);
+        }
+    }
+}
@@ -1,11 +1,9 @@
-use crate::circle::{CirclePoint, MODULUS};
+use crate::circle::CirclePoint;
 use lambdaworks_math::field::{
-    element::FieldElement, fields::mersenne31::field::Mersenne31Field as M31,
+    element::FieldElement, errors::FieldError, fields::mersenne31::field::Mersenne31Field as M31,
+    traits::IsField,
 };
-use ndarray::{s, Array, ArrayBase, Axis, Dim, Dimension, IxDynImpl};
 use std::any::Any;
-use crate::circle::{CirclePoint}
-use crate::merkle::{hash};
 
 const BASE : u32 = 2;
 pub const HALF : u32 = BASE.pow(30);
@@ -17,74 +15,75 @@ pub fn log2(x: u32) -> usize {
     (x as f64).log2() as usize
 }
 
-pub fn reverse_bit_order<D>(vals: &Array<u32, D>) -> Array<u32, D>
-where
-    D: Dimension,
-{
-    let size = vals.len_of(Axis(0));
-    let log_size = (size as u32).ilog2() as usize;
-    let mut current = vals.to_owned();
-
-    for i in 0..log_size {
-        let sub_blocks = 1 << i;
-        let block_size = size >> i;
-
-        // Reshape the array
-        let reshaped = current
-            .to_shape((sub_blocks, block_size))
-            .expect("Reshape failed");
-
-        // Create output array
-        let mut output = Array::zeros(reshaped.raw_dim());
-
-        // Split each block into left and right halves
-        for (block_idx, block) in reshaped.rows().into_iter().enumerate() {
-            let half_len = block.len() / 2;
-
-            // Left half goes in first half of output
-            output
-                .slice_mut(s![block_idx, ..half_len])
-                .assign(&block.slice(s![..;2]));
-
-            // Right half goes in second half of output
-            output
-                .slice_mut(s![block_idx, half_len..])
-                .assign(&block.slice(s![1..;2]));
+// reverses the lowest log_size bits of i
+pub fn bit_reverse_index(i: usize, log_size: u32) -> usize {
+    if log_size == 0 {
+        return i;
+    }
+    i.reverse_bits() >> (usize::BITS - log_size)
+}
+
+// permutes vals into bit reversed order in place, vals.len() must be a power of 2
+pub fn bit_reverse<T>(vals: &mut [T]) {
+    let size = vals.len();
+    assert!(size.is_power_of_two());
+    let log_size = size.ilog2();
+    for i in 0..size {
+        let j = bit_reverse_index(i, log_size);
+        if i < j {
+            vals.swap(i, j);
         }
+    }
+}
 
-        current = output; //yet to check
+// inverts every value with a single field inversion (Montgomery's trick): prefix products,
+// one inverse of the total, then back to front. Works for M31 and its extensions
+// fails with InvZeroError, leaving values untouched, if any of them is zero
+pub fn batch_inverse_in_place<F: IsField>(values: &mut [FieldElement<F>]) -> Result<(), FieldError> {
+    if values.iter().any(|v| *v == FieldElement::zero()) {
+        return Err(FieldError::InvZeroError);
     }
+    batch_inverse_skipping_zeros(values);
+    Ok(())
+}
 
-    // Reshape back to original shape
-    current
+pub fn batch_inverse<F: IsField>(values: &[FieldElement<F>]) -> Result<Vec<FieldElement<F>>, FieldError> {
+    let mut inverses = values.to_vec();
+    batch_inverse_in_place(&mut inverses)?;
+    Ok(inverses)
 }
 
-pub fn folded_reverse_bit_order<D>(vals: &Array<u32, D>) -> Array<u32, D>
-where
-    D:,
-    D: Dimension,
-{
-    // Create an output array of the same shape as input
-    let mut output = Array::zeros(vals.raw_dim());
-
-    // Handle even indices (0, 2, 4, ...)
-    let even_indices = vals.slice(s![..;2]);
-    let reversed_even = reverse_bit_order(&even_indices);
-    output.slice_mut(s![..;2]).assign(&reversed_even);
-
-    // Handle odd indices (1, 3, 5, ...), but reverse the order before and after bit reversal
-    let odd_indices = vals.slice(s![1..;2]);
-    let reversed_odd = reverse_bit_order(&odd_indices.slice(s![..;-1])).slice(s![..;-1]);
-    output.slice_mut(s![1..;2]).assign(&reversed_odd);
-
-    output
+// same as batch_inverse_in_place, but zeros are left as zeros instead of failing
+pub fn batch_inverse_skipping_zeros<F: IsField>(values: &mut [FieldElement<F>]) {
+    let zero = FieldElement::<F>::zero();
+    // prefixes[i] is the product of the nonzero values before i
+    let mut prefixes = Vec::with_capacity(values.len());
+    let mut acc = FieldElement::<F>::one();
+    for v in values.iter() {
+        prefixes.push(acc.clone());
+        if *v != zero {
+            acc = &acc * v;
+        }
+    }
+    // acc is a product of nonzero values, so it is invertible
+    let mut inv = acc.inv().unwrap();
+    for (v, prefix) in values.iter_mut().zip(prefixes).rev() {
+        if *v == zero {
+            continue;
+        }
+        let inv_v = &inv * prefix;
+        inv = &inv * &*v;
+        *v = inv_v;
+    }
 }
 
+// (3^a xor 7^a) mod p for a in len..2*len, the powers wrap around at 2^64 like the uint64
+// numpy arrays of the python original
 pub fn mk_junk_data(len: u32) -> Vec<FieldElement<M31>> {
     (len..2 * len)
         .map(|a| {
-            let ans = 3u32.pow(a) ^ (7u32.pow(a));
-            FieldElement::new(ans)
+            let ans = 3u64.wrapping_pow(a) ^ 7u64.wrapping_pow(a);
+            FieldElement::from(ans)
         })
         .collect()
 }
@@ -95,48 +94,90 @@ pub fn reshape(
     rows: usize,
     cols: usize,
 ) -> Vec<Vec<FieldElement<M31>>> {
+    assert_eq!(data.len(), rows * cols);
     data.chunks(cols).map(|chunk| chunk.to_vec()).collect()
 }
 
-pub fn get_challenges(root: &[u8], domain_size: u32, num_challenges: usize) -> Vec<u32>{
-    let mut challenge_data = Vec::new();
-    for i in 0..((num_challenges + 7) / 8) {
-        let mut hash_input = Vec::new();
-        hash_input.extend_from_slice(root);
-        hash_input.push((i / 256) as u8);
-        hash_input.push((i % 256) as u8);
-        let hash_output = hash(hash_input);
-        challenge_data.extend_from_slice(&hash_output);
-    }
+// deterministic pseudo random values for the tests: the top 31 bits of a 64 bit LCG
+#[cfg(test)]
+pub fn random_values(n: usize, seed: u64) -> Vec<FieldElement<M31>> {
+    let mut state = seed;
+    (0..n)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            FieldElement::from(state >> 33)
+        })
+        .collect()
+}
 
-    (0..num_challenges)
-        .map(|i| {
-            let start = i * 4;
-            let end = start + 4;
-            let value = u32::from_le_bytes(
-                challenge_data[start..end]
-                    .try_into()
-                    .expect("Invalid slice length"),
-            );
-            value % domain_size
-        }).collect()
+// the field axioms on every three consecutive values, for the tests of the extensions
+#[cfg(test)]
+pub fn check_field_axioms<F: IsField>(values: &[FieldElement<F>]) {
+    let (zero, one) = (FieldElement::<F>::zero(), FieldElement::<F>::one());
+    for w in values.windows(3) {
+        let (a, b, c) = (&w[0], &w[1], &w[2]);
+        assert_eq!(a + b, b + a);
+        assert_eq!(a * b, b * a);
+        assert_eq!((a + b) + c, a + (b + c));
+        assert_eq!((a * b) * c, a * (b * c));
+        assert_eq!(a * (b + c), a * b + a * c);
+        assert_eq!(a + &zero, *a);
+        assert_eq!(a * &one, *a);
+        assert_eq!(a * &zero, zero);
+        assert_eq!(a + -a, zero);
+        assert_eq!(a - b, a + -b);
+        assert_eq!(a.square(), a * a);
+        if *a != zero {
+            assert_eq!(a * a.inv().unwrap(), one);
+            assert_eq!(b / a * a, *b);
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cm31::CM31;
+    use crate::qm31::QM31;
+
+    #[test]
+    fn batch_inverse_matches_single_inversions() {
+        let values: Vec<FieldElement<M31>> = (1..100u64).map(|i| FieldElement::from(i * i * 31 + 5)).collect();
+        let inverses = batch_inverse(&values).unwrap();
+        for (v, inv) in values.iter().zip(&inverses) {
+            assert_eq!(*inv, v.inv().unwrap());
+        }
 
-pub fn rbo_index_to_original<D>(length: usize, index: &Array<usize,D>, first_round: bool) ->  Array<usize,D> where D: Dimension {
-    if length == 1{
-        return Array::zeros(index.raw_dim());
+        let values: Vec<FieldElement<QM31>> = (1..20u64)
+            .map(|i| {
+                FieldElement::new([
+                    FieldElement::<CM31>::new([i.into(), (i + 1).into()]),
+                    FieldElement::<CM31>::new([(3 * i).into(), 7.into()]),
+                ])
+            })
+            .collect();
+        let inverses = batch_inverse(&values).unwrap();
+        for (v, inv) in values.iter().zip(&inverses) {
+            assert_eq!(v * inv, FieldElement::one());
+        }
     }
-    let shifted = index>>1;
-    let sub = rbo_index_to_original(length>>1, &shifted, false);
-
-    if first_round{
-        let sub_clone = sub.clone();
-        let res = (1 - (index % 2)) * sub*2 + (index % 2) * (length - 1 - sub_clone*2);
-        return res;
-    } else {
-        let sub_clone = sub.clone();
-        let res = (1 - (index % 2)) * sub + (index % 2) * (length/2 + sub_clone);
-        return res;
+
+    #[test]
+    fn batch_inverse_handles_zeros() {
+        let mut values: Vec<FieldElement<M31>> = [3u64, 0, 5, 0, 9].iter().map(|v| FieldElement::from(*v)).collect();
+        let original = values.clone();
+        assert!(matches!(batch_inverse_in_place(&mut values), Err(FieldError::InvZeroError)));
+        assert_eq!(values, original);
+
+        batch_inverse_skipping_zeros(&mut values);
+        for (v, inv) in original.iter().zip(&values) {
+            match v.inv() {
+                Ok(expected) => assert_eq!(*inv, expected),
+                Err(_) => assert_eq!(*inv, FieldElement::zero()),
+            }
+        }
+        assert!(batch_inverse::<M31>(&[]).unwrap().is_empty());
     }
-}
\ No newline at end of file
+}